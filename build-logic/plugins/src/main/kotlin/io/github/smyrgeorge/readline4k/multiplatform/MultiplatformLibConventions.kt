@@ -95,6 +95,7 @@ class MultiplatformLibConventions : Plugin<Project> {
     private fun KotlinNativeTarget.rust(target: String, useCross: Boolean = false) {
         val tasks = project.tasks
         fun file(path: String) = project.projectDir.resolve(path)
+        val features = Utils.cargoFeaturesOf(project)
 
         compilations["main"].cinterops {
             create("ffi") {
@@ -117,6 +118,7 @@ class MultiplatformLibConventions : Plugin<Project> {
                                 "--target=$target",
                                 "--release"
                             )
+                            if (features.isNotEmpty()) args("--features", features.joinToString(","))
                         }
                     }
                 }
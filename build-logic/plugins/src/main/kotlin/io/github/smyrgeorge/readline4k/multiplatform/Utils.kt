@@ -73,4 +73,19 @@ object Utils {
                 else -> it.split(",").map { t -> t.trim() }
             }
         } ?: listOf(defaultTarget) // Default for local development.
+
+    /**
+     * Returns the optional Cargo features the native library is built with.
+     *
+     * The "cargoFeatures" property holds a comma-separated list of features, e.g.
+     * `-PcargoFeatures=sqlite-history`. If the property is not present, no optional feature is enabled.
+     *
+     * @return a list of Cargo feature names for the project
+     */
+    fun cargoFeaturesOf(project: Project): List<String> =
+        (project.properties["cargoFeatures"] as? String)
+            ?.split(",")
+            ?.map { it.trim() }
+            ?.filter { it.isNotEmpty() }
+            ?: emptyList()
 }
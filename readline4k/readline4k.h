@@ -3,10 +3,21 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+typedef struct CompletionCandidate {
+  uint8_t *display;
+  int display_len;
+  uint8_t *replacement;
+  int replacement_len;
+  uint8_t *description;
+  int description_len;
+} CompletionCandidate;
+
 typedef struct ReadLineResult {
   int error;
   char *error_message;
+  int os_error;
   char *result;
+  int result_len;
 } ReadLineResult;
 
 typedef struct EditorConfig {
@@ -28,64 +39,392 @@ typedef struct EditorConfig {
   bool enable_bracketed_paste;
   bool enable_synchronized_output;
   bool enable_signals;
+  int32_t history_backend;
+  const uint8_t *history_path;
+  int history_path_len;
 } EditorConfig;
 
-typedef char *(*CompleterCallCb)(void *k_callback_holder, const char *line, int pos, int *out_start);
+/**
+ * Opaque handle to an editor created by `new_editor_with_config`, 0 is never a valid handle.
+ */
+typedef uint64_t EditorHandle;
+
+/**
+ * Completes the word at `pos` in `line`. Returns the candidates, null if there are none,
+ * writing their number to `out_len` and the start of the text they replace to `out_start`.
+ *
+ * The array must be allocated with `readline4k_alloc_candidates(*out_len)` and its strings with
+ * `readline4k_alloc_string`; the library takes ownership of all of them.
+ *
+ * Strings are passed to this and the other callbacks as UTF-8 buffers with their length in
+ * bytes, only borrowed for the call; positions are byte offsets. Strings returned by callbacks
+ * must be allocated with `readline4k_alloc_string`, the library takes ownership of them and
+ * replaces invalid UTF-8 with U+FFFD.
+ */
+typedef struct CompletionCandidate *(*CompleterCallCb)(void *k_callback_holder,
+                                                       const uint8_t *line,
+                                                       int line_len,
+                                                       int pos,
+                                                       int *out_start,
+                                                       int *out_len);
 
-typedef char *(*HighlighterCb)(void *k_callback_holder, const char *line, int pos);
+/**
+ * Returns the hint shown after the cursor, null for none, writing its length to `out_len`.
+ * The hint must be allocated with `readline4k_alloc_string`, the library takes ownership of it.
+ */
+typedef uint8_t *(*HinterCb)(void *k_callback_holder,
+                             const uint8_t *line,
+                             int line_len,
+                             int pos,
+                             int *out_len);
 
-typedef char *(*HintHighlighterCb)(void *k_callback_holder, const char *hint);
+/**
+ * Returns `line` styled for display, null to show it as is, writing its length to `out_len`.
+ * The result must be allocated with `readline4k_alloc_string`, the library takes ownership of it.
+ */
+typedef uint8_t *(*HighlighterCb)(void *k_callback_holder,
+                                  const uint8_t *line,
+                                  int line_len,
+                                  int pos,
+                                  int *out_len);
 
-typedef char *(*PromptHighlighterCb)(void *k_callback_holder, const char *prompt, bool is_default);
+/**
+ * Returns `hint` styled for display, null to show it as is. Allocated like [HighlighterCb].
+ */
+typedef uint8_t *(*HintHighlighterCb)(void *k_callback_holder,
+                                      const uint8_t *hint,
+                                      int hint_len,
+                                      int *out_len);
 
-typedef char *(*CandidateHighlighterCb)(void *k_callback_holder,
-                                        const char *candidate,
-                                        int completion);
+/**
+ * Returns `prompt` styled for display, null to show it as is. Allocated like [HighlighterCb].
+ */
+typedef uint8_t *(*PromptHighlighterCb)(void *k_callback_holder,
+                                        const uint8_t *prompt,
+                                        int prompt_len,
+                                        bool is_default,
+                                        int *out_len);
 
-typedef bool (*CharHighlighterCb)(void *k_callback_holder, const char *line, int pos, int kind);
+/**
+ * Returns a completion `candidate` styled for display, null to show it as is. Allocated like
+ * [HighlighterCb].
+ */
+typedef uint8_t *(*CandidateHighlighterCb)(void *k_callback_holder,
+                                           const uint8_t *candidate,
+                                           int candidate_len,
+                                           int completion,
+                                           int *out_len);
 
-typedef int (*ValidatorCb)(void *k_callback_holder, const char *line, int pos, char **out_message);
+/**
+ * Returns whether `line` must be highlighted again after a command of `kind` (0 = MOVE_CURSOR,
+ * 1 = OTHER, 2 = FORCED_REFRESH) left the cursor at `pos`. `line` is only borrowed for the call,
+ * and nothing is returned for the library to free.
+ */
+typedef bool (*CharHighlighterCb)(void *k_callback_holder,
+                                  const uint8_t *line,
+                                  int line_len,
+                                  int pos,
+                                  int kind);
+
+/**
+ * Validates `line` and optionally writes a message to `out_message`, with its length to
+ * `out_message_len`. The message must be allocated with `readline4k_alloc_string`, the library
+ * takes ownership of it whatever the result.
+ *
+ * `pos` is the cursor position when `trigger` is ENTER. Otherwise (e.g. when the line is
+ * accepted with Ctrl-J, or by a command bound to another key) it is the end of `line`.
+ */
+typedef int (*ValidatorCb)(void *k_callback_holder,
+                           const uint8_t *line,
+                           int line_len,
+                           int pos,
+                           int trigger,
+                           uint8_t **out_message,
+                           int *out_message_len);
 
 typedef bool (*ValidatorWhileTypingCb)(void *k_callback_holder);
 
+typedef struct EditorKeyEvent {
+  int32_t code;
+  uint32_t ch;
+  uint8_t modifiers;
+} EditorKeyEvent;
+
+typedef struct EditorCommand {
+  int32_t kind;
+  int32_t movement;
+  uint16_t repeat;
+  uint32_t ch;
+  /**
+   * INSERT, UTF-8. Written by an `EventHandlerCb`, it must be allocated with
+   * `readline4k_alloc_string` and the library takes ownership of it; passed to
+   * `editor_bind_sequence`, it is only borrowed for the call.
+   */
+  const uint8_t *text;
+  int text_len;
+  bool flag;
+} EditorCommand;
+
+/**
+ * Picks the command run for a bound key sequence, written to `out_cmd`. `hint` is null if
+ * there is none. The `text` of the command must be allocated with `readline4k_alloc_string`,
+ * the library takes ownership of it even if the callback returns false.
+ */
+typedef bool (*EventHandlerCb)(void *k_callback_holder,
+                               int handler_id,
+                               const uint8_t *line,
+                               int line_len,
+                               int pos,
+                               int repeat,
+                               bool positive,
+                               const uint8_t *hint,
+                               int hint_len,
+                               struct EditorCommand *out_cmd);
+
+/**
+ * Opaque handle to a read started by `editor_read_line_async`, 0 is never a valid handle.
+ */
+typedef uint64_t ReadRequestHandle;
+
+/**
+ * Opaque handle to a printer created by `editor_create_external_printer`, 0 is never a valid handle.
+ */
+typedef uint64_t PrinterHandle;
+
+/**
+ * Copies the `len` bytes at `bytes` into a buffer owned by the library, null if they are not
+ * a valid buffer. Strings returned by callbacks must be allocated with it, along with their
+ * length in bytes; the buffer of an empty string is not null.
+ */
+uint8_t *readline4k_alloc_string(const uint8_t *bytes, int len);
+
+/**
+ * Frees a string of `len` bytes allocated by `readline4k_alloc_string`, does nothing if `ptr`
+ * is null.
+ */
+void readline4k_free_string(uint8_t *ptr, int len);
+
+/**
+ * Allocates `len` completion candidates with null strings, or returns null if `len` is not
+ * positive. Arrays returned by the completer callback must be allocated with it.
+ */
+struct CompletionCandidate *readline4k_alloc_candidates(int len);
+
+/**
+ * Frees a result returned by the library, with its strings.
+ */
 void free_read_line_result(struct ReadLineResult *ptr);
 
-void *new_editor_with_config(const struct EditorConfig *cfg, void *k_callback_holder);
+struct ReadLineResult *new_editor_with_config(const struct EditorConfig *cfg,
+                                              void *k_callback_holder,
+                                              EditorHandle *out_editor);
+
+/**
+ * Creates an editor attached to a pseudo-terminal of `columns` x `rows` instead of the real one.
+ *
+ * rustyline only talks to the standard input and output, so they are redirected to the
+ * pseudo-terminal, for the whole process, while the editor uses it (e.g. during a read). Only one
+ * headless editor can do so at a time; the calls of the others fail with `ERROR_BUSY` meanwhile.
+ */
+struct ReadLineResult *new_headless_editor(const struct EditorConfig *cfg,
+                                           void *k_callback_holder,
+                                           int columns,
+                                           int rows,
+                                           EditorHandle *out_editor);
+
+struct ReadLineResult *editor_set_completer(EditorHandle rl, CompleterCallCb cb);
+
+struct ReadLineResult *editor_set_hinter(EditorHandle rl, HinterCb cb, int history_hints);
+
+struct ReadLineResult *editor_set_highlighter(EditorHandle rl, HighlighterCb cb);
+
+struct ReadLineResult *editor_set_hint_highlighter(EditorHandle rl, HintHighlighterCb cb);
+
+struct ReadLineResult *editor_set_prompt_highlighter(EditorHandle rl, PromptHighlighterCb cb);
+
+struct ReadLineResult *editor_set_candidate_highlighter(EditorHandle rl, CandidateHighlighterCb cb);
+
+struct ReadLineResult *editor_set_char_highlighter(EditorHandle rl, CharHighlighterCb cb);
+
+struct ReadLineResult *editor_set_validator(EditorHandle rl, ValidatorCb cb);
+
+struct ReadLineResult *editor_set_validator_while_typing(EditorHandle rl,
+                                                         ValidatorWhileTypingCb cb);
+
+struct ReadLineResult *editor_unset_completer(EditorHandle rl);
+
+struct ReadLineResult *editor_unset_hinter(EditorHandle rl);
+
+struct ReadLineResult *editor_unset_highlighter(EditorHandle rl);
+
+struct ReadLineResult *editor_unset_hint_highlighter(EditorHandle rl);
+
+struct ReadLineResult *editor_unset_prompt_highlighter(EditorHandle rl);
+
+struct ReadLineResult *editor_unset_candidate_highlighter(EditorHandle rl);
+
+struct ReadLineResult *editor_unset_char_highlighter(EditorHandle rl);
+
+struct ReadLineResult *editor_unset_validator(EditorHandle rl);
+
+struct ReadLineResult *editor_unset_validator_while_typing(EditorHandle rl);
+
+struct ReadLineResult *editor_reset_helper(EditorHandle rl, void *k_callback_holder);
+
+struct ReadLineResult *editor_bind_sequence(EditorHandle rl,
+                                            const struct EditorKeyEvent *keys,
+                                            int keys_len,
+                                            const struct EditorCommand *cmd,
+                                            bool *out_replaced);
+
+struct ReadLineResult *editor_bind_sequence_handler(EditorHandle rl,
+                                                    const struct EditorKeyEvent *keys,
+                                                    int keys_len,
+                                                    EventHandlerCb cb,
+                                                    int handler_id,
+                                                    bool *out_replaced);
+
+struct ReadLineResult *editor_unbind_sequence(EditorHandle rl,
+                                              const struct EditorKeyEvent *keys,
+                                              int keys_len,
+                                              bool *out_removed);
+
+/**
+ * Writes the `keys_len` keys at `keys` to `out_keys` as they are bound, e.g. `Ctrl-a` as `Ctrl-A`.
+ */
+struct ReadLineResult *readline4k_normalize_key_sequence(const struct EditorKeyEvent *keys,
+                                                         int keys_len,
+                                                         struct EditorKeyEvent *out_keys);
+
+struct ReadLineResult *editor_read_line(EditorHandle rl, const uint8_t *prefix, int prefix_len);
+
+struct ReadLineResult *editor_read_line_with_timeout(EditorHandle rl,
+                                                     const uint8_t *prefix,
+                                                     int prefix_len,
+                                                     int timeout_ms,
+                                                     bool idle);
+
+struct ReadLineResult *editor_read_line_with_initial(EditorHandle rl,
+                                                     const uint8_t *prefix,
+                                                     int prefix_len,
+                                                     const uint8_t *left,
+                                                     int left_len,
+                                                     const uint8_t *right,
+                                                     int right_len);
+
+struct ReadLineResult *editor_read_password(EditorHandle rl,
+                                            const uint8_t *prefix,
+                                            int prefix_len,
+                                            uint32_t mask,
+                                            int timeout_ms);
+
+struct ReadLineResult *editor_cancel_read_line(EditorHandle rl, bool *out_cancelled);
+
+struct ReadLineResult *editor_feed_input(EditorHandle rl, const uint8_t *input, int input_len);
+
+struct ReadLineResult *editor_feed_keys(EditorHandle rl,
+                                        const struct EditorKeyEvent *keys,
+                                        int keys_len);
+
+struct ReadLineResult *editor_take_output(EditorHandle rl);
+
+struct ReadLineResult *editor_read_line_async(EditorHandle rl,
+                                              const uint8_t *prefix,
+                                              int prefix_len,
+                                              ReadRequestHandle *out_request);
+
+struct ReadLineResult *read_request_poll(ReadRequestHandle request, bool *out_done);
+
+struct ReadLineResult *read_request_await(ReadRequestHandle request,
+                                          int timeout_ms,
+                                          bool *out_done);
+
+struct ReadLineResult *read_request_cancel(ReadRequestHandle request, bool *out_cancelled);
+
+void free_read_request(ReadRequestHandle request);
+
+struct ReadLineResult *editor_load_history(EditorHandle rl, const uint8_t *path, int path_len);
+
+struct ReadLineResult *editor_add_history_entry(EditorHandle rl,
+                                                const uint8_t *entry,
+                                                int entry_len);
+
+struct ReadLineResult *editor_save_history(EditorHandle rl, const uint8_t *path, int path_len);
+
+struct ReadLineResult *editor_append_history(EditorHandle rl,
+                                             const uint8_t *path,
+                                             int path_len,
+                                             bool merge);
+
+struct ReadLineResult *editor_clear_history(EditorHandle rl);
+
+struct ReadLineResult *editor_history_len(EditorHandle rl, int *out_len);
+
+struct ReadLineResult *editor_history_get(EditorHandle rl, int index);
+
+struct ReadLineResult *editor_history_search(EditorHandle rl,
+                                             const uint8_t *term,
+                                             int term_len,
+                                             int start,
+                                             int direction,
+                                             int *out_idx,
+                                             int *out_pos);
+
+struct ReadLineResult *editor_history_starts_with(EditorHandle rl,
+                                                  const uint8_t *term,
+                                                  int term_len,
+                                                  int start,
+                                                  int direction,
+                                                  int *out_idx,
+                                                  int *out_pos);
+
+struct ReadLineResult *editor_history_get_details(EditorHandle rl,
+                                                  int index,
+                                                  int64_t *out_session_id,
+                                                  int64_t *out_timestamp);
+
+struct ReadLineResult *editor_history_session_id(EditorHandle rl, int64_t *out_session_id);
+
+struct ReadLineResult *editor_clear_screen(EditorHandle rl);
+
+struct ReadLineResult *editor_set_cursor_visibility(EditorHandle rl, bool visible);
 
-void editor_set_completer(void *rl, CompleterCallCb cb);
+struct ReadLineResult *editor_set_auto_add_history(EditorHandle rl, bool value);
 
-void editor_set_highlighter(void *rl, HighlighterCb cb);
+struct ReadLineResult *editor_set_color_mode(EditorHandle rl, int value);
 
-void editor_set_hint_highlighter(void *rl, HintHighlighterCb cb);
+struct ReadLineResult *editor_get_config(EditorHandle rl, struct EditorConfig *out_config);
 
-void editor_set_prompt_highlighter(void *rl, PromptHighlighterCb cb);
+struct ReadLineResult *editor_set_edit_mode(EditorHandle rl, int value);
 
-void editor_set_candidate_highlighter(void *rl, CandidateHighlighterCb cb);
+struct ReadLineResult *editor_set_completion_type(EditorHandle rl, int value);
 
-void editor_set_char_highlighter(void *rl, CharHighlighterCb cb);
+struct ReadLineResult *editor_set_completion_show_all_if_ambiguous(EditorHandle rl, bool value);
 
-void editor_set_validator(void *rl, ValidatorCb cb);
+struct ReadLineResult *editor_set_completion_prompt_limit(EditorHandle rl, int value);
 
-void editor_set_validator_while_typing(void *rl, ValidatorWhileTypingCb cb);
+struct ReadLineResult *editor_set_max_history_size(EditorHandle rl, int value);
 
-struct ReadLineResult *editor_read_line(void *rl, const char *prefix);
+struct ReadLineResult *editor_set_history_duplicates(EditorHandle rl, int value);
 
-struct ReadLineResult *editor_load_history(void *rl, const char *path);
+struct ReadLineResult *editor_set_history_ignore_space(EditorHandle rl, bool value);
 
-void editor_add_history_entry(void *rl, const char *entry);
+struct ReadLineResult *editor_set_key_seq_timeout(EditorHandle rl, int value);
 
-struct ReadLineResult *editor_save_history(void *rl, const char *path);
+struct ReadLineResult *editor_set_bell_style(EditorHandle rl, int value);
 
-struct ReadLineResult *editor_clear_history(void *rl);
+struct ReadLineResult *editor_set_tab_stop(EditorHandle rl, uint8_t value);
 
-struct ReadLineResult *editor_clear_screen(void *rl);
+struct ReadLineResult *editor_set_indent_size(EditorHandle rl, uint8_t value);
 
-void editor_set_cursor_visibility(void *rl, bool visible);
+struct ReadLineResult *editor_set_check_cursor_position(EditorHandle rl, bool value);
 
-void editor_set_auto_add_history(void *rl, bool value);
+struct ReadLineResult *editor_create_external_printer(EditorHandle rl, PrinterHandle *out_printer);
 
-void editor_set_color_mode(void *rl, int value);
+struct ReadLineResult *external_printer_print(PrinterHandle printer,
+                                              const uint8_t *msg,
+                                              int msg_len);
 
-void free_editor(void *ptr);
+void free_external_printer(PrinterHandle printer);
 
-extern void free(void *ptr);
+void free_editor(EditorHandle rl);
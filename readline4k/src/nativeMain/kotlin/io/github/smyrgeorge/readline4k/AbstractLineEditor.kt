@@ -1,46 +1,106 @@
 package io.github.smyrgeorge.readline4k
 
+import io.github.smyrgeorge.readline4k.LineEditorError.Companion.couldNotCreateTheExternalPrinter
 import io.github.smyrgeorge.readline4k.LineEditorError.Companion.couldNotInstantiateTheEditor
 import io.github.smyrgeorge.readline4k.LineEditorError.Companion.editorIsDisposed
 import io.github.smyrgeorge.readline4k.impl.candidateHighlighterCallback
 import io.github.smyrgeorge.readline4k.impl.charHighlighterCallback
 import io.github.smyrgeorge.readline4k.impl.completerCallback
+import io.github.smyrgeorge.readline4k.impl.eventHandlerCallback
 import io.github.smyrgeorge.readline4k.impl.highlighterCallback
+import io.github.smyrgeorge.readline4k.impl.indexOfUtf8Offset
+import io.github.smyrgeorge.readline4k.impl.hinterCallback
 import io.github.smyrgeorge.readline4k.impl.hintHighlighterCallback
+import io.github.smyrgeorge.readline4k.impl.normalized
 import io.github.smyrgeorge.readline4k.impl.promptHighlighterCallback
+import io.github.smyrgeorge.readline4k.impl.toCArray
 import io.github.smyrgeorge.readline4k.impl.toCValue
+import io.github.smyrgeorge.readline4k.impl.toLineEditorConfig
+import io.github.smyrgeorge.readline4k.impl.toNullableStringResult
 import io.github.smyrgeorge.readline4k.impl.toStringResult
 import io.github.smyrgeorge.readline4k.impl.toUnitResult
+import io.github.smyrgeorge.readline4k.impl.utf8Bytes
 import io.github.smyrgeorge.readline4k.impl.validatorCallback
 import io.github.smyrgeorge.readline4k.impl.validatorWhileTypingCallback
+import kotlinx.cinterop.BooleanVar
 import kotlinx.cinterop.COpaquePointer
 import kotlinx.cinterop.CValue
 import kotlinx.cinterop.ExperimentalForeignApi
+import kotlinx.cinterop.IntVar
+import kotlinx.cinterop.LongVar
 import kotlinx.cinterop.StableRef
+import kotlinx.cinterop.alloc
 import kotlinx.cinterop.memScoped
+import kotlinx.cinterop.ptr
 import kotlinx.cinterop.staticCFunction
+import kotlinx.cinterop.value
 import kotlinx.io.files.Path
 import kotlinx.io.files.SystemFileSystem
 import readline4k.EditorConfig
+import readline4k.EditorHandle
+import readline4k.EditorHandleVar
+import readline4k.PrinterHandleVar
+import readline4k.ReadRequestHandleVar
 import readline4k.editor_add_history_entry
+import readline4k.editor_append_history
+import readline4k.editor_bind_sequence
+import readline4k.editor_bind_sequence_handler
+import readline4k.editor_cancel_read_line
 import readline4k.editor_clear_history
 import readline4k.editor_clear_screen
+import readline4k.editor_create_external_printer
+import readline4k.editor_get_config
+import readline4k.editor_history_get
+import readline4k.editor_history_get_details
+import readline4k.editor_history_len
+import readline4k.editor_history_search
+import readline4k.editor_history_session_id
+import readline4k.editor_history_starts_with
 import readline4k.editor_load_history
 import readline4k.editor_read_line
+import readline4k.editor_read_line_async
+import readline4k.editor_read_line_with_timeout
+import readline4k.editor_read_line_with_initial
+import readline4k.editor_read_password
+import readline4k.editor_reset_helper
 import readline4k.editor_save_history
 import readline4k.editor_set_auto_add_history
+import readline4k.editor_set_bell_style
 import readline4k.editor_set_candidate_highlighter
 import readline4k.editor_set_char_highlighter
+import readline4k.editor_set_check_cursor_position
 import readline4k.editor_set_color_mode
 import readline4k.editor_set_completer
+import readline4k.editor_set_completion_prompt_limit
+import readline4k.editor_set_completion_show_all_if_ambiguous
+import readline4k.editor_set_completion_type
 import readline4k.editor_set_cursor_visibility
+import readline4k.editor_set_edit_mode
 import readline4k.editor_set_highlighter
+import readline4k.editor_set_hinter
 import readline4k.editor_set_hint_highlighter
+import readline4k.editor_set_history_duplicates
+import readline4k.editor_set_history_ignore_space
+import readline4k.editor_set_indent_size
+import readline4k.editor_set_key_seq_timeout
+import readline4k.editor_set_max_history_size
 import readline4k.editor_set_prompt_highlighter
+import readline4k.editor_set_tab_stop
 import readline4k.editor_set_validator
 import readline4k.editor_set_validator_while_typing
+import readline4k.editor_unbind_sequence
+import readline4k.editor_unset_candidate_highlighter
+import readline4k.editor_unset_char_highlighter
+import readline4k.editor_unset_completer
+import readline4k.editor_unset_highlighter
+import readline4k.editor_unset_hinter
+import readline4k.editor_unset_hint_highlighter
+import readline4k.editor_unset_prompt_highlighter
+import readline4k.editor_unset_validator
+import readline4k.editor_unset_validator_while_typing
 import readline4k.free_editor
 import readline4k.new_editor_with_config
+import readline4k.new_headless_editor
 
 /**
  * Abstract base for interactive line editors backed by a native engine.
@@ -58,27 +118,47 @@ import readline4k.new_editor_with_config
  * Notes on behavior and threading:
  * - All operations interact with a native resource created at construction time. The resource is
  *   freed by the underlying runtime when the process exits; you do not need to close it manually.
- * - Instances are not intended to be used concurrently from multiple threads.
- * - Methods returning Result wrap native errors into [LineEditorError].
+ * - Instances are not intended to be used concurrently from multiple threads, except for
+ *   [cancelReadLine] which is meant to be called while another thread is blocked in [readLine].
+ *   [readLineAsync] reads on a native worker thread instead; see [ReadLineRequest].
+ * - Methods returning Result wrap native errors into [LineEditorError]. Invalid arguments and
+ *   unexpected failures of the native engine are reported the same way, with
+ *   [LineEditorError.Code.InvalidArgument] and [LineEditorError.Code.Panic], instead of aborting the process.
  *
  * @property linePrefix The prompt/prefix displayed before each input (e.g., "> ").
  * @property config The immutable configuration used to initialize the native editor.
  */
 @OptIn(ExperimentalForeignApi::class)
-abstract class AbstractLineEditor(
+abstract class AbstractLineEditor internal constructor(
     val linePrefix: String,
     val config: LineEditorConfig,
+    headlessSize: Pair<Int, Int>?,
 ) : AutoCloseable {
+    constructor(linePrefix: String, config: LineEditorConfig) : this(linePrefix, config, null)
+
     private val holder: CallbacksHolder = CallbacksHolder()
     private val holderRef: StableRef<CallbacksHolder> = StableRef.create(holder)
     private val holderPointer: COpaquePointer = holderRef.asCPointer()
 
-    private var _rl: COpaquePointer? = memScoped {
-        val cfg: CValue<EditorConfig> = config.toCValue()
-        new_editor_with_config(cfg.ptr, holderPointer) ?: couldNotInstantiateTheEditor()
+    private var _rl: EditorHandle? = memScoped {
+        val cfg: CValue<EditorConfig> = config.toCValue(this)
+        val editor = alloc<EditorHandleVar>()
+        val created = if (headlessSize == null) new_editor_with_config(cfg.ptr, holderPointer, editor.ptr)
+        else new_headless_editor(cfg.ptr, holderPointer, headlessSize.first, headlessSize.second, editor.ptr)
+        created.toUnitResult().getOrElse {
+            holderRef.dispose()
+            throw it
+        }
+        editor.value.takeIf { it != 0uL } ?: couldNotInstantiateTheEditor()
     }
 
-    private val rl: COpaquePointer = _rl ?: editorIsDisposed()
+    // Once disposed, the native side rejects this handle with LineEditorError.Code.Disposed.
+    internal val rl: EditorHandle = _rl ?: editorIsDisposed()
+
+    // Keyed by the normalized key sequence, the one the native binding is matched on.
+    private val boundEventHandlers: MutableMap<List<KeyEvent>, Int> = mutableMapOf()
+    private var nextEventHandlerId: Int = 0
+    private val readRequests: MutableList<ReadLineRequest> = mutableListOf()
 
     /**
      * Reads a single line of input from the user, optionally displaying a [prefix] at the start of the line.
@@ -89,18 +169,147 @@ abstract class AbstractLineEditor(
      * @param prefix The optional string to display as a prompt at the start of the line. Defaults to the editor's [linePrefix].
      * @return A [Result] containing the read line as a [String] on success, or an error description on failure.
      */
-    fun readLine(prefix: String = linePrefix): Result<String> = editor_read_line(rl, prefix).toStringResult()
+    fun readLine(prefix: String = linePrefix): Result<String> {
+        val prefixBytes = prefix.utf8Bytes()
+        return editor_read_line(rl, prefixBytes, prefixBytes.size).toStringResult()
+    }
+
+    /**
+     * Reads a single line of input from the user, giving up after [timeoutMillis].
+     *
+     * When [idle] is true, the timeout restarts with every key typed, so the read only fails if the
+     * user stays inactive (e.g. to log out idle sessions). Otherwise, the whole line must be entered
+     * within [timeoutMillis]. On timeout, the read fails with [LineEditorError.Code.Timeout] and the
+     * terminal is restored to the mode it had before the read.
+     *
+     * Like [cancelReadLine], this is only supported when reading from an interactive terminal on Unix;
+     * otherwise it fails with [LineEditorError.Code.Unsupported].
+     *
+     * @param timeoutMillis The timeout, in milliseconds.
+     * @param idle Whether the timeout restarts with every key typed. Defaults to true.
+     * @param prefix The optional string to display as a prompt at the start of the line. Defaults to the editor's [linePrefix].
+     * @return A [Result] containing the read line as a [String] on success, or an error description on failure.
+     */
+    fun readLineWithTimeout(
+        timeoutMillis: Int,
+        idle: Boolean = true,
+        prefix: String = linePrefix,
+    ): Result<String> {
+        val prefixBytes = prefix.utf8Bytes()
+        return editor_read_line_with_timeout(rl, prefixBytes, prefixBytes.size, timeoutMillis, idle).toStringResult()
+    }
+
+    /**
+     * Reads a single line of input from the user, with the input buffer pre-filled.
+     *
+     * The buffer initially contains [left] followed by [right], with the cursor placed between them.
+     * This is useful for "edit previous command" flows, or to re-prompt with the user's text after
+     * a validation failure.
+     *
+     * @param left The initial text placed before the cursor.
+     * @param right The initial text placed after the cursor. Defaults to an empty string.
+     * @param prefix The optional string to display as a prompt at the start of the line. Defaults to the editor's [linePrefix].
+     * @return A [Result] containing the read line as a [String] on success, or an error description on failure.
+     */
+    fun readLineWithInitial(
+        left: String,
+        right: String = "",
+        prefix: String = linePrefix,
+    ): Result<String> {
+        val prefixBytes = prefix.utf8Bytes()
+        val leftBytes = left.utf8Bytes()
+        val rightBytes = right.utf8Bytes()
+        return editor_read_line_with_initial(
+            rl,
+            prefixBytes, prefixBytes.size,
+            leftBytes, leftBytes.size,
+            rightBytes, rightBytes.size,
+        ).toStringResult()
+    }
+
+    /**
+     * Reads a password, echoing every character typed as [mask].
+     *
+     * Unlike a [readLine] with a [io.github.smyrgeorge.readline4k.impl.PasswordHighlighter], the input
+     * is read by a separate native editor: it never reaches the hinter, completer, highlighter,
+     * validator, or key bindings of this editor, it is neither added to nor browsed through the
+     * history, and text killed while typing it cannot be yanked back in a later read. The password
+     * is still copied around in memory (the buffers of the separate editor are freed without being
+     * wiped), so keep the returned [String] no longer than needed.
+     *
+     * The input is masked while colors are enabled, see [LineEditorConfig.colorMode]. If the
+     * password would be echoed as typed instead (colors disabled, or [LineEditorConfig.ColorMode.ENABLED]
+     * with an output that is not a terminal), this fails with [LineEditorError.Code.Unsupported].
+     *
+     * The read can be cancelled with [cancelReadLine], like [readLine].
+     *
+     * @param prefix The optional string to display as a prompt at the start of the line. Defaults to the editor's [linePrefix].
+     * @param mask The character echoed for every character typed, not a control character. Defaults to '*'.
+     * @param idleTimeoutMillis If not null, the read fails with [LineEditorError.Code.Timeout] once no key was
+     *   typed for that long, see [readLineWithTimeout].
+     * @return A [Result] containing the password as a [String] on success, or an error description on failure.
+     */
+    fun readPassword(
+        prefix: String = linePrefix,
+        mask: Char = '*',
+        idleTimeoutMillis: Int? = null,
+    ): Result<String> {
+        val prefixBytes = prefix.utf8Bytes()
+        return editor_read_password(rl, prefixBytes, prefixBytes.size, mask.code.toUInt(), idleTimeoutMillis ?: -1)
+            .toStringResult()
+    }
+
+    /**
+     * Cancel the [readLine] (or [readLineWithInitial], [readLineWithTimeout]) call currently blocked on this editor.
+     *
+     * Safe to call from any thread. The pending read fails with [LineEditorError.Code.Cancelled]
+     * and the terminal is restored to the mode it had before the read.
+     *
+     * Only reads from an interactive terminal on Unix can be cancelled; otherwise this fails
+     * with [LineEditorError.Code.Unsupported].
+     *
+     * @return true if a pending read was cancelled, false if there was none.
+     */
+    fun cancelReadLine(): Result<Boolean> = memScoped {
+        val cancelled = alloc<BooleanVar>()
+        editor_cancel_read_line(rl, cancelled.ptr).toUnitResult().map { cancelled.value }
+    }
+
+    /**
+     * Start reading a line in the background and return immediately.
+     *
+     * The read runs on a native worker thread; use the returned [ReadLineRequest] to poll, await,
+     * or cancel it. While the read is in progress the callbacks ([Completer], [Highlighter], etc.)
+     * are invoked from the worker thread, and the other methods of this editor that need the
+     * native editor fail with [LineEditorError.Code.Busy].
+     *
+     * @param prefix The optional string to display as a prompt at the start of the line. Defaults to the editor's [linePrefix].
+     * @return the pending read; call [ReadLineRequest.close] once its result is no longer needed.
+     */
+    fun readLineAsync(prefix: String = linePrefix): Result<ReadLineRequest> = memScoped {
+        val request = alloc<ReadRequestHandleVar>()
+        val prefixBytes = prefix.utf8Bytes()
+        editor_read_line_async(rl, prefixBytes, prefixBytes.size, request.ptr).toUnitResult().map {
+            readRequests.removeAll { it.closed }
+            ReadLineRequest(request.value).also { readRequests.add(it) }
+        }
+    }
 
     /**
      * Load history entries from the given file [path].
      *
      * If the file does not exist, this is a no-op and returns success. When it exists, entries
      * are appended/replaced according to the native backend policy and current [config].
+     * With an SQLite history, the database at [path] is used from now on.
+     *
+     * Fails with [LineEditorError.Code.Unsupported] for a [LineEditorConfig.HistoryBackend.Memory]
+     * or [LineEditorConfig.HistoryBackend.Disabled] history, if the file exists.
      */
     fun loadHistory(path: String): Result<Unit> {
         val exists = SystemFileSystem.exists(Path(path))
-        return if (!exists) Result.success(Unit)
-        else editor_load_history(rl, path).toUnitResult()
+        if (!exists) return Result.success(Unit)
+        val pathBytes = path.utf8Bytes()
+        return editor_load_history(rl, pathBytes, pathBytes.size).toUnitResult()
     }
 
     /**
@@ -108,29 +317,179 @@ abstract class AbstractLineEditor(
      *
      * Whether duplicates are kept depends on [LineEditorConfig.historyDuplicates].
      */
-    fun addHistoryEntry(entry: String): Unit = editor_add_history_entry(rl, entry)
+    fun addHistoryEntry(entry: String): Result<Unit> {
+        val entryBytes = entry.utf8Bytes()
+        return editor_add_history_entry(rl, entryBytes, entryBytes.size).toUnitResult()
+    }
 
     /**
      * Save current history to the file at [path]. Creates or overwrites as needed.
+     *
+     * The whole file is rewritten, dropping entries other editors saved to it in the meantime;
+     * use [appendHistory] when several editors share the same file.
+     *
+     * An SQLite history stores entries as they are added; saving to another [path] writes a copy
+     * of the database.
+     *
+     * Fails with [LineEditorError.Code.Unsupported] for a [LineEditorConfig.HistoryBackend.Memory]
+     * or [LineEditorConfig.HistoryBackend.Disabled] history, which are never written to a file.
+     */
+    fun saveHistory(path: String): Result<Unit> {
+        val pathBytes = path.utf8Bytes()
+        return editor_save_history(rl, pathBytes, pathBytes.size).toUnitResult()
+    }
+
+    /**
+     * Append the entries added since the last save to the file at [path], creating it if needed.
+     *
+     * The file is locked while it is written, and the entries other editors appended to it are
+     * kept, so that concurrent sessions (e.g. several instances of the same REPL) share the same
+     * history file safely. Once the file exceeds [LineEditorConfig.maxHistorySize], the oldest
+     * entries are dropped.
+     *
+     * With [merge], the in-memory history is then reloaded from the file, to also pick up the
+     * entries written by other sessions (like zsh `share_history`).
+     *
+     * Example:
+     * ```kotlin
+     * editor.readLine().onSuccess { line ->
+     *     editor.addHistoryEntry(line)
+     *     editor.appendHistory(history, merge = true)
+     * }
+     * ```
+     *
+     * An SQLite history is always shared, so [merge] has no effect on it. Fails like [saveHistory]
+     * for a history kept in memory only.
      */
-    fun saveHistory(path: String): Result<Unit> = editor_save_history(rl, path).toUnitResult()
+    fun appendHistory(path: String, merge: Boolean = false): Result<Unit> {
+        val pathBytes = path.utf8Bytes()
+        return editor_append_history(rl, pathBytes, pathBytes.size, merge).toUnitResult()
+    }
 
     /**
      * Clear the in-memory history.
      */
     fun clearHistory(): Result<Unit> = editor_clear_history(rl).toUnitResult()
 
+    /**
+     * Number of entries currently held in the in-memory history.
+     */
+    fun historyLength(): Result<Int> = memScoped {
+        val len = alloc<IntVar>()
+        editor_history_len(rl, len.ptr).toUnitResult().map { len.value }
+    }
+
+    /**
+     * Get the history entry at [index], where 0 is the oldest entry.
+     *
+     * @return the entry, or null if [index] is out of range.
+     */
+    fun getHistoryEntry(index: Int): Result<String?> = editor_history_get(rl, index).toNullableStringResult()
+
+    /**
+     * Get a snapshot of all history entries, from the oldest to the newest.
+     */
+    fun historyEntries(): Result<List<String>> = runCatching {
+        (0 until historyLength().getOrThrow()).mapNotNull { getHistoryEntry(it).getOrThrow() }
+    }
+
+    /**
+     * Search the history for an entry containing [term], starting at index [start] and
+     * scanning in the given [direction].
+     *
+     * By default, the search starts at the newest entry and goes towards older ones.
+     *
+     * @return the first match, or null if no entry contains [term].
+     */
+    fun searchHistory(
+        term: String,
+        start: Int = historyLength().getOrDefault(0) - 1,
+        direction: HistorySearchResult.Direction = HistorySearchResult.Direction.REVERSE,
+    ): Result<HistorySearchResult?> = memScoped {
+        val idx = alloc<IntVar>()
+        val pos = alloc<IntVar>()
+        val termBytes = term.utf8Bytes()
+        editor_history_search(rl, termBytes, termBytes.size, start, direction.ordinal, idx.ptr, pos.ptr)
+            .toNullableStringResult()
+            .map { entry -> entry?.let { HistorySearchResult(it, idx.value, it.indexOfUtf8Offset(pos.value)) } }
+    }
+
+    /**
+     * Search the history for an entry starting with [term], starting at index [start] and
+     * scanning in the given [direction].
+     *
+     * By default, the search starts at the newest entry and goes towards older ones.
+     *
+     * @return the first match, or null if no entry starts with [term].
+     */
+    fun searchHistoryPrefix(
+        term: String,
+        start: Int = historyLength().getOrDefault(0) - 1,
+        direction: HistorySearchResult.Direction = HistorySearchResult.Direction.REVERSE,
+    ): Result<HistorySearchResult?> = memScoped {
+        val idx = alloc<IntVar>()
+        val pos = alloc<IntVar>()
+        val termBytes = term.utf8Bytes()
+        editor_history_starts_with(rl, termBytes, termBytes.size, start, direction.ordinal, idx.ptr, pos.ptr)
+            .toNullableStringResult()
+            .map { entry -> entry?.let { HistorySearchResult(it, idx.value, it.indexOfUtf8Offset(pos.value)) } }
+    }
+
+    /**
+     * Get the history entry at [index] along with the session that added it and when.
+     *
+     * Only an SQLite history stored in a file records these details (see
+     * [LineEditorConfig.HistoryBackend.Sqlite]); otherwise, this fails with
+     * [LineEditorError.Code.Unsupported]. Entries added by other editors sharing the database
+     * are included.
+     *
+     * @return the entry, or null if there is no entry at [index].
+     */
+    fun getHistoryEntryDetails(index: Int): Result<HistoryEntry?> = memScoped {
+        val sessionId = alloc<LongVar>()
+        val timestamp = alloc<LongVar>()
+        editor_history_get_details(rl, index, sessionId.ptr, timestamp.ptr)
+            .toNullableStringResult()
+            .map { entry -> entry?.let { HistoryEntry(it, index, sessionId.value, timestamp.value) } }
+    }
+
+    /**
+     * The session of this editor in its SQLite history, to tell its entries apart from the ones
+     * added by other editors (see [HistoryEntry.sessionId]).
+     *
+     * Fails like [getHistoryEntryDetails] if the history does not record sessions.
+     *
+     * @return the session ID, or 0 until this editor adds an entry.
+     */
+    fun historySessionId(): Result<Long> = memScoped {
+        val sessionId = alloc<LongVar>()
+        editor_history_session_id(rl, sessionId.ptr).toUnitResult().map { sessionId.value }
+    }
+
     /**
      * Clears the terminal screen using the native line editor's rendering capabilities.
      */
     fun clearScreen(): Result<Unit> = editor_clear_screen(rl).toUnitResult()
 
+    /**
+     * Create an [ExternalPrinter] that prints messages above the prompt, from any thread,
+     * without corrupting the line being edited.
+     *
+     * Fails if the editor is not attached to a terminal that supports it.
+     */
+    fun createExternalPrinter(): Result<ExternalPrinter> = memScoped {
+        val printer = alloc<PrinterHandleVar>()
+        editor_create_external_printer(rl, printer.ptr).toUnitResult().mapCatching {
+            ExternalPrinter(printer.value.takeIf { it != 0uL } ?: couldNotCreateTheExternalPrinter())
+        }
+    }
+
     /**
      * Show or hide the terminal cursor while the editor is active.
      *
      * @param visible true to show the cursor; false to hide it.
      */
-    fun setCursorVisibility(visible: Boolean) = editor_set_cursor_visibility(rl, visible)
+    fun setCursorVisibility(visible: Boolean): Result<Unit> = editor_set_cursor_visibility(rl, visible).toUnitResult()
 
     /**
      * Enable or disable automatic addition of accepted lines to the history buffer.
@@ -140,7 +499,7 @@ abstract class AbstractLineEditor(
      *
      * @param value true to enable auto-add; false to require manual calls to [addHistoryEntry].
      */
-    fun setAutoAddHistory(value: Boolean) = editor_set_auto_add_history(rl, value)
+    fun setAutoAddHistory(value: Boolean): Result<Unit> = editor_set_auto_add_history(rl, value).toUnitResult()
 
     /**
      * Set the color rendering mode used by the editor for prompts, hints, and highlights.
@@ -151,15 +510,200 @@ abstract class AbstractLineEditor(
      *
      * @param value The desired color mode.
      */
-    fun setColorMode(value: LineEditorConfig.ColorMode) = editor_set_color_mode(rl, value.ordinal)
+    fun setColorMode(value: LineEditorConfig.ColorMode): Result<Unit> =
+        editor_set_color_mode(rl, value.ordinal).toUnitResult()
+
+    /*
+     * Runtime configuration, e.g. to toggle `:set vi` inside a running session. Changes apply from
+     * the next read; [config] keeps the values the editor was created with, see [effectiveConfig].
+     */
+
+    /**
+     * Get the configuration in effect: [config] with the changes made at runtime (e.g. with
+     * [setEditMode] or [setAutoAddHistory]) applied.
+     *
+     * Useful to display the current settings, or to persist them and create the next editor with.
+     */
+    fun effectiveConfig(): Result<LineEditorConfig> = memScoped {
+        val cfg = alloc<EditorConfig>()
+        editor_get_config(rl, cfg.ptr).toNullableStringResult().map { cfg.toLineEditorConfig(it) }
+    }
+
+    /**
+     * Switch between Emacs and Vi editing, see [LineEditorConfig.editMode].
+     *
+     * The key sequence timeout is reset to the default of the mode: none for Emacs, 500 ms for
+     * Vi. Call [setKeySeqTimeout] afterward to override it.
+     */
+    fun setEditMode(value: LineEditorConfig.EditMode): Result<Unit> =
+        editor_set_edit_mode(rl, value.ordinal).toUnitResult()
+
+    /**
+     * Set how completion candidates are applied or listed, see [LineEditorConfig.completionType].
+     */
+    fun setCompletionType(value: LineEditorConfig.CompletionType): Result<Unit> =
+        editor_set_completion_type(rl, value.ordinal).toUnitResult()
+
+    /**
+     * See [LineEditorConfig.completionShowAllIfAmbiguous].
+     */
+    fun setCompletionShowAllIfAmbiguous(value: Boolean): Result<Unit> =
+        editor_set_completion_show_all_if_ambiguous(rl, value).toUnitResult()
+
+    /**
+     * See [LineEditorConfig.completionPromptLimit].
+     */
+    fun setCompletionPromptLimit(value: Int): Result<Unit> =
+        editor_set_completion_prompt_limit(rl, value).toUnitResult()
+
+    /**
+     * Set the maximum number of history entries, see [LineEditorConfig.maxHistorySize].
+     *
+     * The oldest entries that no longer fit are dropped right away.
+     */
+    fun setMaxHistorySize(value: Int): Result<Unit> = editor_set_max_history_size(rl, value).toUnitResult()
+
+    /**
+     * See [LineEditorConfig.historyDuplicates].
+     */
+    fun setHistoryDuplicates(value: LineEditorConfig.HistoryDuplicates): Result<Unit> =
+        editor_set_history_duplicates(rl, value.ordinal).toUnitResult()
+
+    /**
+     * See [LineEditorConfig.historyIgnoreSpace].
+     */
+    fun setHistoryIgnoreSpace(value: Boolean): Result<Unit> =
+        editor_set_history_ignore_space(rl, value).toUnitResult()
+
+    /**
+     * Set how long to wait for the rest of an ambiguous key sequence, in milliseconds, or null
+     * for no timeout. See [LineEditorConfig.keySeqTimeout].
+     *
+     * Fails with [LineEditorError.Code.InvalidArgument] if [value] is negative or above 65535.
+     */
+    fun setKeySeqTimeout(value: Int?): Result<Unit> = editor_set_key_seq_timeout(rl, value ?: -1).toUnitResult()
+
+    /**
+     * See [LineEditorConfig.bellStyle].
+     */
+    fun setBellStyle(value: LineEditorConfig.BellStyle): Result<Unit> =
+        editor_set_bell_style(rl, value.ordinal).toUnitResult()
+
+    /**
+     * Set the width of a tab character, see [LineEditorConfig.tabStop].
+     *
+     * Fails with [LineEditorError.Code.InvalidArgument] unless [value] is between 1 and 255.
+     */
+    fun setTabStop(value: Int): Result<Unit> {
+        if (value !in 1..255) {
+            return Result.failure(LineEditorError(LineEditorError.Code.InvalidArgument, "Invalid tab stop: $value"))
+        }
+        return editor_set_tab_stop(rl, value.toUByte()).toUnitResult()
+    }
+
+    /**
+     * Set the indentation size used by the indent and dedent commands, see [LineEditorConfig.indentSize].
+     *
+     * Fails with [LineEditorError.Code.InvalidArgument] unless [value] is between 0 and 255.
+     */
+    fun setIndentSize(value: Int): Result<Unit> {
+        if (value !in 0..255) {
+            return Result.failure(LineEditorError(LineEditorError.Code.InvalidArgument, "Invalid indent size: $value"))
+        }
+        return editor_set_indent_size(rl, value.toUByte()).toUnitResult()
+    }
+
+    /**
+     * See [LineEditorConfig.checkCursorPosition].
+     */
+    fun setCheckCursorPosition(value: Boolean): Result<Unit> =
+        editor_set_check_cursor_position(rl, value).toUnitResult()
+
+    /**
+     * Bind a key sequence to an editing [command], on top of the active Emacs/Vi keymap.
+     *
+     * Custom bindings take precedence over the default keymap, so this can be used both to add
+     * new shortcuts and to remap existing ones (bind to [Command.Noop] to disable a key).
+     *
+     * @param keys The key sequence, e.g. `listOf(KeyEvent.ctrl('X'), KeyEvent.ctrl('E'))`.
+     * @param command The command to execute when the sequence is typed.
+     * @return true if a previous binding for the same sequence was replaced.
+     */
+    fun bindSequence(keys: List<KeyEvent>, command: Command): Result<Boolean> = memScoped {
+        releaseEventHandler(keys)
+        val replaced = alloc<BooleanVar>()
+        editor_bind_sequence(rl, keys.toCArray(this), keys.size, command.toCValue(this), replaced.ptr)
+            .toUnitResult()
+            .map { replaced.value }
+    }
+
+    /**
+     * Bind a key sequence to an [EventHandler] that chooses the command to run each time
+     * the sequence is typed, based on the current line, cursor position and repeat count.
+     *
+     * @param keys The key sequence, e.g. `listOf(KeyEvent.ctrl('T'))`.
+     * @param handler The handler consulted when the sequence is typed.
+     * @return true if a previous binding for the same sequence was replaced.
+     */
+    fun bindSequence(keys: List<KeyEvent>, handler: EventHandler): Result<Boolean> = memScoped {
+        releaseEventHandler(keys)
+        val id = nextEventHandlerId++
+        val replaced = alloc<BooleanVar>()
+        editor_bind_sequence_handler(
+            rl,
+            keys.toCArray(this),
+            keys.size,
+            staticCFunction(::eventHandlerCallback),
+            id,
+            replaced.ptr
+        ).toUnitResult().map {
+            holder.eventHandlers[id] = handler
+            boundEventHandlers[keys.normalized().getOrDefault(keys)] = id
+            replaced.value
+        }
+    }
+
+    /**
+     * Remove a custom binding previously installed with [bindSequence].
+     *
+     * @param keys The key sequence to unbind.
+     * @return true if a binding existed and was removed.
+     */
+    fun unbindSequence(keys: List<KeyEvent>): Result<Boolean> = memScoped {
+        releaseEventHandler(keys)
+        val removed = alloc<BooleanVar>()
+        editor_unbind_sequence(rl, keys.toCArray(this), keys.size, removed.ptr)
+            .toUnitResult()
+            .map { removed.value }
+    }
+
+    private fun releaseEventHandler(keys: List<KeyEvent>) {
+        boundEventHandlers.remove(keys.normalized().getOrDefault(keys))?.let { holder.eventHandlers.remove(it) }
+    }
 
     /**
      * Install a [Completer] which will be consulted during completion (e.g., Tab).
      * Returns this editor instance for chaining.
      */
     fun setCompleter(completer: Completer): AbstractLineEditor {
+        editor_set_completer(rl, staticCFunction(::completerCallback)).toUnitResult().getOrThrow()
         holder.completer = completer
-        editor_set_completer(rl, staticCFunction(::completerCallback))
+        return this
+    }
+
+    /**
+     * Install a [Hinter] that provides inline hints shown after the cursor while typing.
+     * Returns this editor instance for chaining.
+     *
+     * @param hinter The [Hinter] to consult.
+     * @param historyHints How hints from history are combined with the hints of [hinter].
+     */
+    fun setHinter(
+        hinter: Hinter,
+        historyHints: Hinter.HistoryHints = Hinter.HistoryHints.FALLBACK,
+    ): AbstractLineEditor {
+        editor_set_hinter(rl, staticCFunction(::hinterCallback), historyHints.ordinal).toUnitResult().getOrThrow()
+        holder.hinter = hinter
         return this
     }
 
@@ -168,12 +712,12 @@ abstract class AbstractLineEditor(
      * Returns this editor instance for chaining.
      */
     fun setHighlighter(highlighter: Highlighter): AbstractLineEditor {
+        editor_set_highlighter(rl, staticCFunction(::highlighterCallback)).toUnitResult().getOrThrow()
+        editor_set_hint_highlighter(rl, staticCFunction(::hintHighlighterCallback)).toUnitResult().getOrThrow()
+        editor_set_prompt_highlighter(rl, staticCFunction(::promptHighlighterCallback)).toUnitResult().getOrThrow()
+        editor_set_candidate_highlighter(rl, staticCFunction(::candidateHighlighterCallback)).toUnitResult().getOrThrow()
+        editor_set_char_highlighter(rl, staticCFunction(::charHighlighterCallback)).toUnitResult().getOrThrow()
         holder.highlighter = highlighter
-        editor_set_highlighter(rl, staticCFunction(::highlighterCallback))
-        editor_set_hint_highlighter(rl, staticCFunction(::hintHighlighterCallback))
-        editor_set_prompt_highlighter(rl, staticCFunction(::promptHighlighterCallback))
-        editor_set_candidate_highlighter(rl, staticCFunction(::candidateHighlighterCallback))
-        editor_set_char_highlighter(rl, staticCFunction(::charHighlighterCallback))
         return this
     }
 
@@ -186,9 +730,70 @@ abstract class AbstractLineEditor(
      * @return The current [AbstractLineEditor] instance, enabling chained method calls.
      */
     fun setValidator(validator: Validator): AbstractLineEditor {
+        editor_set_validator(rl, staticCFunction(::validatorCallback)).toUnitResult().getOrThrow()
+        editor_set_validator_while_typing(rl, staticCFunction(::validatorWhileTypingCallback)).toUnitResult().getOrThrow()
         holder.validator = validator
-        editor_set_validator(rl, staticCFunction(::validatorCallback))
-        editor_set_validator_while_typing(rl, staticCFunction(::validatorWhileTypingCallback))
+        return this
+    }
+
+    /**
+     * Remove the [Completer] installed with [setCompleter]; Tab then completes nothing.
+     * Returns this editor instance for chaining.
+     */
+    fun unsetCompleter(): AbstractLineEditor {
+        editor_unset_completer(rl).toUnitResult().getOrThrow()
+        holder.completer = null
+        return this
+    }
+
+    /**
+     * Remove the [Hinter] installed with [setHinter]; only the hints from history are shown then.
+     * Returns this editor instance for chaining.
+     */
+    fun unsetHinter(): AbstractLineEditor {
+        editor_unset_hinter(rl).toUnitResult().getOrThrow()
+        holder.hinter = null
+        return this
+    }
+
+    /**
+     * Remove the [Highlighter] installed with [setHighlighter], e.g. to stop highlighting SQL when
+     * switching to a plain-text input step. Returns this editor instance for chaining.
+     */
+    fun unsetHighlighter(): AbstractLineEditor {
+        editor_unset_highlighter(rl).toUnitResult().getOrThrow()
+        editor_unset_hint_highlighter(rl).toUnitResult().getOrThrow()
+        editor_unset_prompt_highlighter(rl).toUnitResult().getOrThrow()
+        editor_unset_candidate_highlighter(rl).toUnitResult().getOrThrow()
+        editor_unset_char_highlighter(rl).toUnitResult().getOrThrow()
+        holder.highlighter = null
+        return this
+    }
+
+    /**
+     * Remove the [Validator] installed with [setValidator]; every line is accepted then.
+     * Returns this editor instance for chaining.
+     */
+    fun unsetValidator(): AbstractLineEditor {
+        editor_unset_validator(rl).toUnitResult().getOrThrow()
+        editor_unset_validator_while_typing(rl).toUnitResult().getOrThrow()
+        holder.validator = null
+        return this
+    }
+
+    /**
+     * Remove the [Completer], [Hinter], [Highlighter], and [Validator] of this editor at once.
+     * Key bindings installed with [bindSequence] are kept. Returns this editor instance for chaining.
+     *
+     * Like the other `set` and `unset` methods, this throws a [LineEditorError] with
+     * [LineEditorError.Code.Busy] if called while a read is in progress.
+     */
+    fun resetHelper(): AbstractLineEditor {
+        editor_reset_helper(rl, holderPointer).toUnitResult().getOrThrow()
+        holder.completer = null
+        holder.hinter = null
+        holder.highlighter = null
+        holder.validator = null
         return this
     }
 
@@ -201,13 +806,24 @@ abstract class AbstractLineEditor(
      * components.
      *
      * Specifically, this method:
+     * - Cancels and awaits the reads started with [readLineAsync] that are still in progress.
      * - Frees the underlying native editor resources through [free_editor].
-     * - Disposes of the reference held by [holderRef].
+     * - Disposes of the reference held by [holderRef], unless a background read could not be cancelled.
      * - Sets any nullable references, such as [_rl], to null to assist in garbage collection.
+     *
+     * Calling it more than once has no effect. Any other call on a disposed editor fails
+     * with [LineEditorError.Code.Disposed].
      */
     fun dispose() {
+        if (_rl == null) return
+        // Background reads still call back into the holder, so it is only released once they stopped.
+        // Closing a request already waited for its read.
+        val stopped = readRequests.filterNot { it.closed }.all { request ->
+            request.cancel().isSuccess && request.await() != null
+        }
+        readRequests.clear()
         free_editor(rl)
-        holderRef.dispose()
+        if (stopped) holderRef.dispose()
         _rl = null
     }
 
@@ -228,7 +844,9 @@ abstract class AbstractLineEditor(
      */
     internal class CallbacksHolder(
         var completer: Completer? = null,
+        var hinter: Hinter? = null,
         var highlighter: Highlighter? = null,
         var validator: Validator? = null,
+        val eventHandlers: MutableMap<Int, EventHandler> = mutableMapOf(),
     )
 }
\ No newline at end of file
@@ -0,0 +1,172 @@
+package io.github.smyrgeorge.readline4k
+
+/**
+ * An editing command that can be bound to a key sequence via [AbstractLineEditor.bindSequence].
+ *
+ * Commands mirror the actions of the native engine's default keymaps (Emacs/Vi), so a custom
+ * binding can remap any key to behave like any built-in action, or insert arbitrary text.
+ *
+ * Example:
+ * ```kotlin
+ * // Make Ctrl-J insert a newline instead of accepting the line.
+ * editor.bindSequence(listOf(KeyEvent.ctrl('J')), Command.Newline)
+ * // Ctrl-Up searches history backwards for entries sharing the typed prefix.
+ * editor.bindSequence(listOf(KeyEvent.of(KeyEvent.Code.UP, KeyEvent.Modifier.CTRL)), Command.HistorySearchBackward)
+ * ```
+ */
+sealed class Command {
+    /** Do nothing (useful to disable a default binding). */
+    data object Noop : Command()
+
+    /** Abort the current action (e.g. an incremental search). */
+    data object Abort : Command()
+
+    /** Accept the current line. */
+    data object AcceptLine : Command()
+
+    /**
+     * Accept the line if it is valid, otherwise insert a newline.
+     *
+     * @property acceptInTheMiddle whether the line is accepted when the cursor is not at the end of the input.
+     */
+    data class AcceptOrInsertLine(val acceptInTheMiddle: Boolean = true) : Command()
+
+    /** Move to the first entry in history. */
+    data object BeginningOfHistory : Command()
+
+    /** Move to the last entry in history (the line being edited). */
+    data object EndOfHistory : Command()
+
+    /** Capitalize the current word. */
+    data object CapitalizeWord : Command()
+
+    /** Lower-case the next word. */
+    data object DowncaseWord : Command()
+
+    /** Upper-case the next word. */
+    data object UpcaseWord : Command()
+
+    /** Clear the screen and redraw the prompt. */
+    data object ClearScreen : Command()
+
+    /** Complete (next candidate). */
+    data object Complete : Command()
+
+    /** Complete (previous candidate). */
+    data object CompleteBackward : Command()
+
+    /** Accept the currently displayed hint. */
+    data object CompleteHint : Command()
+
+    /** End of file (Ctrl-D on an empty line). */
+    data object EndOfFile : Command()
+
+    /** Incremental search forward in history. */
+    data object ForwardSearchHistory : Command()
+
+    /** Incremental search backward in history. */
+    data object ReverseSearchHistory : Command()
+
+    /** Search history backward for entries starting with the text before the cursor. */
+    data object HistorySearchBackward : Command()
+
+    /** Search history forward for entries starting with the text before the cursor. */
+    data object HistorySearchForward : Command()
+
+    /** Insert [text] at the cursor, [repeat] times. */
+    data class Insert(val text: String, val repeat: Int = 1) : Command()
+
+    /** Interrupt the current read (Ctrl-C). */
+    data object Interrupt : Command()
+
+    /** Delete the text covered by [movement]. */
+    data class Kill(val movement: Movement, val repeat: Int = 1) : Command()
+
+    /** Move the cursor according to [movement]. */
+    data class Move(val movement: Movement, val repeat: Int = 1) : Command()
+
+    /** Go to the next history entry. */
+    data object NextHistory : Command()
+
+    /** Go to the previous history entry. */
+    data object PreviousHistory : Command()
+
+    /** Insert a newline. */
+    data object Newline : Command()
+
+    /** Insert the next typed character literally. */
+    data object QuotedInsert : Command()
+
+    /** Redraw the current line. */
+    data object Repaint : Command()
+
+    /** Insert the character [c], [repeat] times. */
+    data class SelfInsert(val c: Char, val repeat: Int = 1) : Command()
+
+    /** Suspend the process (Unix only). */
+    data object Suspend : Command()
+
+    /** Transpose the previous character with the current character. */
+    data object TransposeChars : Command()
+
+    /** Transpose words. */
+    data class TransposeWords(val repeat: Int = 1) : Command()
+
+    /** Undo the last [repeat] changes. */
+    data class Undo(val repeat: Int = 1) : Command()
+
+    /**
+     * Paste from the yank buffer.
+     *
+     * @property beforeCursor true to paste before the cursor, false to paste after it.
+     */
+    data class Yank(val repeat: Int = 1, val beforeCursor: Boolean = false) : Command()
+
+    /** Replace the previously yanked text with the next entry in the kill ring. */
+    data object YankPop : Command()
+
+    /** Indent the lines covered by [movement]. */
+    data class Indent(val movement: Movement, val repeat: Int = 1) : Command()
+
+    /** Dedent the lines covered by [movement]. */
+    data class Dedent(val movement: Movement, val repeat: Int = 1) : Command()
+
+    /** Move to the line above, or to the previous history entry when on the first line. */
+    data class LineUpOrPreviousHistory(val repeat: Int = 1) : Command()
+
+    /** Move to the line below, or to the next history entry when on the last line. */
+    data class LineDownOrNextHistory(val repeat: Int = 1) : Command()
+
+    /**
+     * A cursor movement (or range) used by [Kill], [Move], [Indent] and [Dedent].
+     */
+    enum class Movement {
+        // IMPORTANT: Do not change the order of the movements.
+        /** The whole current line */
+        WHOLE_LINE,
+        BEGINNING_OF_LINE,
+        END_OF_LINE,
+
+        /** Backward until the start of a word */
+        BACKWARD_WORD,
+
+        /** Forward until after the end of a word */
+        FORWARD_WORD,
+        BACKWARD_CHAR,
+        FORWARD_CHAR,
+
+        /** Same column on the previous line */
+        LINE_UP,
+
+        /** Same column on the next line */
+        LINE_DOWN,
+
+        /** The whole user input */
+        WHOLE_BUFFER,
+        BEGINNING_OF_BUFFER,
+        END_OF_BUFFER,
+
+        /** The first non-blank character of the line */
+        FIRST_PRINT
+    }
+}
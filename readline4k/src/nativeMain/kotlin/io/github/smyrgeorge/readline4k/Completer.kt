@@ -9,8 +9,9 @@ package io.github.smyrgeorge.readline4k
  * of the token to replace, and return a list of possible completions for that token.
  *
  * Notes:
- * - The returned candidates are plain strings; any coloring/highlighting of candidates
- *   is handled by [Highlighter].
+ * - The candidates returned by [complete] are plain strings; override [completeCandidates] to
+ *   show a different label (and an optional description) than the text being inserted.
+ *   Any coloring/highlighting of candidates is handled by [Highlighter].
  * - If no completions are available, return an empty list. The editor will handle it
  *   gracefully according to the configured [LineEditorConfig.CompletionType].
  * - The API is platform-agnostic; internals will bridge to the native backend.
@@ -48,4 +49,32 @@ interface Completer {
      *   - items: a list of candidate strings. Return an empty list if there are no matches.
      */
     fun complete(line: String, pos: Int): Pair<Int, List<String>>
+
+    /**
+     * Compute structured completions for the given [line] and caret position [pos].
+     *
+     * This is what the editor actually calls. The default implementation wraps the items
+     * returned by [complete] into [Candidate]s whose display text equals the replacement.
+     * Override it to show, for example, `"name  -- description"` in the candidate list while
+     * inserting only `"name"`.
+     *
+     * @return Pair(start, candidates) with the same semantics as [complete].
+     */
+    fun completeCandidates(line: String, pos: Int): Pair<Int, List<Candidate>> {
+        val (start, items) = complete(line, pos)
+        return start to items.map { Candidate(it) }
+    }
+
+    /**
+     * A completion candidate.
+     *
+     * @property replacement The text inserted into the line when the candidate is chosen.
+     * @property display The text shown in the candidate list. Defaults to [replacement].
+     * @property description Optional text shown after [display] (as `"display  -- description"`).
+     */
+    data class Candidate(
+        val replacement: String,
+        val display: String = replacement,
+        val description: String? = null,
+    )
 }
\ No newline at end of file
@@ -0,0 +1,45 @@
+package io.github.smyrgeorge.readline4k
+
+/**
+ * Decides at runtime which [Command] a bound key sequence should execute.
+ *
+ * Unlike a static binding ([AbstractLineEditor.bindSequence] with a [Command]), an event handler
+ * is consulted every time its key sequence is typed and can inspect the current input state
+ * (line, cursor position, repeat count, displayed hint) before choosing what to do.
+ *
+ * Example:
+ * ```kotlin
+ * // Tab accepts the hint only when the cursor is at the end of the line.
+ * editor.bindSequence(listOf(KeyEvent.of(KeyEvent.Code.TAB)), object : EventHandler {
+ *     override fun handle(context: EventHandler.Context): Command? =
+ *         if (context.hint != null && context.pos == context.line.length) Command.CompleteHint
+ *         else null // keep the default (completion)
+ * })
+ * ```
+ */
+interface EventHandler {
+    /**
+     * Choose the command to execute for the current input state.
+     *
+     * @param context the current input state
+     * @return the command to execute, or null to perform the default action bound to the key
+     */
+    fun handle(context: Context): Command?
+
+    /**
+     * Snapshot of the editor state at the moment the key sequence was typed.
+     *
+     * @property line the current input buffer
+     * @property pos the caret position within [line], as an index into it (0..line.length)
+     * @property repeat the repeat count (numeric argument), 1 by default
+     * @property positive false if the numeric argument is negative
+     * @property hint the hint currently displayed after the cursor, if any
+     */
+    data class Context(
+        val line: String,
+        val pos: Int,
+        val repeat: Int,
+        val positive: Boolean,
+        val hint: String?,
+    )
+}
@@ -0,0 +1,47 @@
+package io.github.smyrgeorge.readline4k
+
+import io.github.smyrgeorge.readline4k.impl.toUnitResult
+import io.github.smyrgeorge.readline4k.impl.utf8Bytes
+import kotlinx.cinterop.ExperimentalForeignApi
+import readline4k.PrinterHandle
+import readline4k.external_printer_print
+import readline4k.free_external_printer
+
+/**
+ * Prints messages above the active input line without corrupting the prompt.
+ *
+ * Printing directly to stdout while [AbstractLineEditor.readLine] is waiting for input mixes the
+ * output with the line being edited. An [ExternalPrinter] instead hands the message to the native
+ * engine, which prints it above the prompt and redraws the prompt and the current input.
+ *
+ * Unlike the editor itself, a printer can be used from any thread (e.g. from background jobs
+ * that emit log lines while the user is typing).
+ *
+ * Example:
+ * ```kotlin
+ * val printer = editor.createExternalPrinter().getOrThrow()
+ * thread { printer.print("job finished") }
+ * editor.readLine()
+ * printer.close()
+ * ```
+ *
+ * Create instances with [AbstractLineEditor.createExternalPrinter]; call [close] when no longer needed.
+ */
+@OptIn(ExperimentalForeignApi::class)
+class ExternalPrinter internal constructor(
+    private val printer: PrinterHandle,
+) : AutoCloseable {
+    /**
+     * Print [msg] above the prompt. A trailing newline is added if missing.
+     */
+    fun print(msg: String): Result<Unit> {
+        val line = if (msg.endsWith('\n')) msg else "$msg\n"
+        val lineBytes = line.utf8Bytes()
+        return external_printer_print(printer, lineBytes, lineBytes.size).toUnitResult()
+    }
+
+    /**
+     * Releases the native printer. Calling [print] afterward fails with [LineEditorError.Code.Disposed].
+     */
+    override fun close(): Unit = free_external_printer(printer)
+}
@@ -0,0 +1,51 @@
+package io.github.smyrgeorge.readline4k
+
+/**
+ * Provides inline hints (fish-style suggestions) displayed after the cursor while the user types.
+ *
+ * By default, the editor only suggests hints from history. Installing a [Hinter] via
+ * [AbstractLineEditor.setHinter] lets you compute hints from your own source (e.g. a command
+ * grammar), optionally combined with history hints according to [HistoryHints].
+ *
+ * Notes:
+ * - The hint is the text that would be appended at the cursor, not the full line.
+ * - Hints can be styled via [Highlighter.highlightHint] and accepted with Right/Ctrl-F at the
+ *   end of the line (see [Command.CompleteHint]).
+ * - This is called on every keystroke; keep it fast.
+ *
+ * Example:
+ * ```kotlin
+ * editor.setHinter(object : Hinter {
+ *     private val commands = listOf("select", "insert", "update", "delete")
+ *     override fun hint(line: String, pos: Int): String? {
+ *         if (line.isEmpty() || pos < line.length) return null
+ *         return commands.firstOrNull { it.startsWith(line) }?.removePrefix(line)
+ *     }
+ * })
+ * ```
+ */
+interface Hinter {
+    /**
+     * Compute the hint for the given [line] and caret position [pos].
+     *
+     * @param line the current input buffer
+     * @param pos the caret position within [line], as an index into it (0..line.length)
+     * @return the hint to display after the cursor, or null for no hint
+     */
+    fun hint(line: String, pos: Int): String?
+
+    /**
+     * How history-based hints are combined with the hints of a [Hinter].
+     */
+    enum class HistoryHints {
+        // IMPORTANT: Do not change the order of the modes.
+        /** Only the [Hinter] is consulted. */
+        DISABLED,
+
+        /** The [Hinter] is consulted first; history is used when it returns null. */
+        FALLBACK,
+
+        /** History is consulted first; the [Hinter] is used when history has no match. */
+        PREFERRED
+    }
+}
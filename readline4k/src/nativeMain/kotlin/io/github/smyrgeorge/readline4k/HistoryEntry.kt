@@ -0,0 +1,17 @@
+package io.github.smyrgeorge.readline4k
+
+/**
+ * A history entry with the details recorded by an SQLite history, see
+ * [AbstractLineEditor.getHistoryEntryDetails].
+ *
+ * @property entry The history entry.
+ * @property index The index of [entry] in the history (0 is the oldest entry).
+ * @property sessionId The session that added [entry]; each editor adding entries starts its own session.
+ * @property timestamp When [entry] was added, in milliseconds since the Unix epoch.
+ */
+data class HistoryEntry(
+    val entry: String,
+    val index: Int,
+    val sessionId: Long,
+    val timestamp: Long,
+)
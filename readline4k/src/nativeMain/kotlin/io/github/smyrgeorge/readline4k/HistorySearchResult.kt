@@ -0,0 +1,26 @@
+package io.github.smyrgeorge.readline4k
+
+/**
+ * A history entry found by [AbstractLineEditor.searchHistory] or [AbstractLineEditor.searchHistoryPrefix].
+ *
+ * @property entry The matching history entry.
+ * @property index The index of [entry] in the history (0 is the oldest entry).
+ * @property pos The position of the match within [entry], as an index into it.
+ */
+data class HistorySearchResult(
+    val entry: String,
+    val index: Int,
+    val pos: Int,
+) {
+    /**
+     * Direction in which the history is scanned, starting from the given index.
+     */
+    enum class Direction {
+        // IMPORTANT: Do not change the order of the directions.
+        /** Towards newer entries */
+        FORWARD,
+
+        /** Towards older entries */
+        REVERSE
+    }
+}
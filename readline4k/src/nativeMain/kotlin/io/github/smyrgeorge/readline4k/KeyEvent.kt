@@ -0,0 +1,93 @@
+package io.github.smyrgeorge.readline4k
+
+/**
+ * Describes a single key press (a key code plus the modifiers held down) that can be bound
+ * to a [Command] via [AbstractLineEditor.bindSequence].
+ *
+ * Key sequences are expressed as a list of key events, e.g. `Ctrl-X Ctrl-E` is
+ * `listOf(KeyEvent.ctrl('X'), KeyEvent.ctrl('E'))`.
+ *
+ * Notes:
+ * - Events are normalized by the native engine before being bound: `Ctrl-a` is the same as
+ *   `Ctrl-A`, and `Shift-Tab` is the same as [Code.BACK_TAB].
+ * - For [Code.CHAR] the [value] is the Unicode code point of the character; for [Code.F] it is
+ *   the function key number (e.g. 1 for F1). It is ignored for all other codes.
+ *
+ * Example:
+ * ```kotlin
+ * // Ctrl-T inserts a fixed snippet.
+ * editor.bindSequence(listOf(KeyEvent.ctrl('T')), Command.Insert("SELECT * FROM "))
+ * // Alt-Left moves to the beginning of the line.
+ * editor.bindSequence(listOf(KeyEvent.of(KeyEvent.Code.LEFT, KeyEvent.Modifier.ALT)), Command.Move(Command.Movement.BEGINNING_OF_LINE))
+ * ```
+ *
+ * @property code The key that was pressed.
+ * @property value The character code point or function key number, depending on [code].
+ * @property modifiers The modifier keys held down together with the key.
+ */
+data class KeyEvent(
+    val code: Code,
+    val value: Int = 0,
+    val modifiers: Set<Modifier> = emptySet(),
+) {
+    /**
+     * Input key pressed.
+     */
+    enum class Code {
+        // IMPORTANT: Do not change the order of the codes.
+        /** Single character, see [KeyEvent.value] */
+        CHAR,
+        BACKSPACE,
+
+        /** Usually Shift-Tab */
+        BACK_TAB,
+        DELETE,
+        DOWN,
+        END,
+        ENTER,
+        ESC,
+
+        /** Function key, see [KeyEvent.value] */
+        F,
+        HOME,
+        INSERT,
+        LEFT,
+        PAGE_DOWN,
+        PAGE_UP,
+        RIGHT,
+        TAB,
+        UP
+    }
+
+    /**
+     * Modifier keys that can be held down along with a key press.
+     */
+    enum class Modifier {
+        // IMPORTANT: Do not change the order of the modifiers.
+        SHIFT,
+
+        /** Alt, or Meta/Escape prefix */
+        ALT,
+        CTRL
+    }
+
+    companion object {
+        /** A plain (or modified) character key press. */
+        fun char(c: Char, vararg modifiers: Modifier): KeyEvent =
+            KeyEvent(Code.CHAR, c.code, modifiers.toSet())
+
+        /** Ctrl + [c]. */
+        fun ctrl(c: Char): KeyEvent = char(c, Modifier.CTRL)
+
+        /** Alt/Meta + [c]. */
+        fun alt(c: Char): KeyEvent = char(c, Modifier.ALT)
+
+        /** The function key F[n]. */
+        fun f(n: Int, vararg modifiers: Modifier): KeyEvent =
+            KeyEvent(Code.F, n, modifiers.toSet())
+
+        /** A non-character key such as [Code.LEFT] or [Code.ENTER]. */
+        fun of(code: Code, vararg modifiers: Modifier): KeyEvent =
+            KeyEvent(code, 0, modifiers.toSet())
+    }
+}
@@ -22,15 +22,17 @@ import kotlin.experimental.ExperimentalNativeApi
  * - [enableBracketedPaste], [enableSynchronizedOutput], and [enableSignals] are only meaningful on
  *   Unix-like platforms; they are ignored where unsupported.
  * - [colorMode] can be used to force or disable colors regardless of terminal detection.
+ * - [HistoryBackend.Sqlite] requires the native library to be built with the `sqlite-history`
+ *   Cargo feature (`-PcargoFeatures=sqlite-history`).
  *
  * Properties overview:
- * @property maxHistorySize              Maximum number of entries stored in history before older ones are dropped.
+ * @property maxHistorySize              Maximum number of entries stored in history before older ones are dropped; not negative.
  * @property historyDuplicates           Strategy for handling duplicate consecutive history lines.
  * @property historyIgnoreSpace          If true, lines starting with a space are not added to history.
  * @property completionType              Strategy for how completion is applied/presented.
  * @property completionShowAllIfAmbiguous When LIST mode is used, whether to immediately show all ambiguous matches.
- * @property completionPromptLimit       Maximum number of candidates shown per page when listing matches.
- * @property keySeqTimeout               Milliseconds to wait when reading an ambiguous key sequence; null uses the default.
+ * @property completionPromptLimit       Maximum number of candidates shown per page when listing matches; not negative.
+ * @property keySeqTimeout               Milliseconds (0..65535) to wait when reading an ambiguous key sequence; null uses the default.
  * @property editMode                    Base keymap style (Emacs or Vi).
  * @property autoAddHistory              If true, non-blank lines returned by readLine are auto-added to history.
  * @property bellStyle                   Audible/visible bell behavior when signaling (e.g., invalid key press).
@@ -42,6 +44,7 @@ import kotlin.experimental.ExperimentalNativeApi
  * @property enableBracketedPaste        Enables bracketed paste on Unix-like platforms to avoid accidental execution.
  * @property enableSynchronizedOutput    Enables synchronized output on Unix-like platforms to reduce flicker/tearing.
  * @property enableSignals               If true, termios signals are enabled (Unix); when false, they may be disabled.
+ * @property historyBackend              Where history entries are kept: a file, an SQLite database, memory only, or nowhere.
  */
 data class LineEditorConfig(
     val maxHistorySize: Int = 100,
@@ -61,9 +64,45 @@ data class LineEditorConfig(
     val checkCursorPosition: Boolean = false,
     val enableBracketedPaste: Boolean = true,
     val enableSynchronizedOutput: Boolean = true,
-    val enableSignals: Boolean = false
+    val enableSignals: Boolean = false,
+    val historyBackend: HistoryBackend = HistoryBackend.File,
 ) {
 
+    /**
+     * Storage of the history entries.
+     */
+    sealed interface HistoryBackend {
+        /**
+         * In-memory history, loaded from and saved to a plain text file with
+         * [AbstractLineEditor.loadHistory] and [AbstractLineEditor.saveHistory].
+         */
+        data object File : HistoryBackend
+
+        /**
+         * History stored in an SQLite database as soon as entries are added, so that concurrent
+         * editors (e.g. several shells of the same tool) share it safely. Each entry records the
+         * session that added it and when, see [AbstractLineEditor.getHistoryEntryDetails].
+         *
+         * Creating an editor fails with [LineEditorError.Code.Unsupported] if the native library
+         * was built without the `sqlite-history` feature.
+         *
+         * @property path The database file, created if missing; null keeps the history in memory.
+         */
+        data class Sqlite(val path: String? = null) : HistoryBackend
+
+        /**
+         * In-memory history that can never be persisted, e.g. for sensitive prompts: loading or
+         * saving it fails with [LineEditorError.Code.Unsupported].
+         */
+        data object Memory : HistoryBackend
+
+        /**
+         * No history at all: entries are never added, whatever [maxHistorySize] is set to, and the
+         * history cannot be loaded or saved (as with [Memory]). E.g. for short-lived sub-REPLs.
+         */
+        data object Disabled : HistoryBackend
+    }
+
     /**
      * Controls how the editor signals events such as errors or invalid keys.
      *
@@ -8,24 +8,81 @@ package io.github.smyrgeorge.readline4k
  * or other related functionality.
  *
  * @property code The specific error code indicating the type of error.
+ * @property osError The OS error number (errno) for [Code.Io] and [Code.Errno] errors, if available.
  * @constructor Creates a new `LineEditorError` with the specified error code and an optional message.
  */
 class LineEditorError(
     val code: Code,
     message: String? = null,
+    val osError: Int? = null,
 ) : RuntimeException("[$code] :: $message") {
-    enum class Code {
-        // IMPORTANT: Do not change the order of the errors.
+    /**
+     * Machine-readable error codes. The [value] of each code is stable and matches the
+     * code reported by the underlying driver.
+     */
+    enum class Code(val value: Int) {
+        // IMPORTANT: Do not change the order or the values of the errors.
         // Error from the underlying driver:
-        Eof,
-        Interrupted,
-        Unknown
+        /** End of file (Ctrl-D on an empty line) */
+        Eof(0),
+
+        /** Interrupted (Ctrl-C) */
+        Interrupted(1),
+
+        /** Any other error */
+        Unknown(2),
+
+        /** I/O error, see [osError] */
+        Io(3),
+
+        /** Unix system call error, see [osError] */
+        Errno(4),
+
+        /** The terminal window was resized while reading */
+        WindowResized(5),
+
+        /** The input could not be decoded (invalid UTF-8/UTF-16) */
+        Decode(6),
+
+        /** A signal was received while reading */
+        Signal(7),
+
+        /** A Windows API call failed */
+        System(8),
+
+        /** An invalid argument was passed to the driver (e.g. an unknown enum value or invalid UTF-8) */
+        InvalidArgument(9),
+
+        /** The driver failed unexpectedly; the failure was contained and the editor may be in an inconsistent state */
+        Panic(10),
+
+        /** The editor (or external printer) was already disposed */
+        Disposed(11),
+
+        /** The editor is in use, e.g. by a pending read whose callbacks called back into the editor */
+        Busy(12),
+
+        /** The read was cancelled with [AbstractLineEditor.cancelReadLine] */
+        Cancelled(13),
+
+        /** The operation is not supported on this platform or terminal */
+        Unsupported(14),
+
+        /** The read timed out, see [AbstractLineEditor.readLineWithTimeout] */
+        Timeout(15);
+
+        companion object {
+            /** Returns the code with the given [value], or [Unknown] if there is none. */
+            fun of(value: Int): Code = entries.firstOrNull { it.value == value } ?: Unknown
+        }
     }
 
     companion object {
         internal fun couldNotInstantiateTheEditor(): Nothing =
             throw LineEditorError(Code.Unknown, "Could not instantiate the editor.")
+        internal fun couldNotCreateTheExternalPrinter(): Nothing =
+            throw LineEditorError(Code.Unknown, "Could not create the external printer.")
         internal fun editorIsDisposed(): Nothing =
-            throw LineEditorError(Code.Unknown, "Editor is disposed.")
+            throw LineEditorError(Code.Disposed, "Editor is disposed.")
     }
 }
\ No newline at end of file
@@ -0,0 +1,98 @@
+package io.github.smyrgeorge.readline4k
+
+import io.github.smyrgeorge.readline4k.impl.toNullableStringResult
+import io.github.smyrgeorge.readline4k.impl.toUnitResult
+import kotlinx.cinterop.BooleanVar
+import kotlinx.cinterop.CPointer
+import kotlinx.cinterop.ExperimentalForeignApi
+import kotlinx.cinterop.alloc
+import kotlinx.cinterop.memScoped
+import kotlinx.cinterop.ptr
+import kotlinx.cinterop.value
+import readline4k.ReadLineResult
+import readline4k.ReadRequestHandle
+import readline4k.free_read_request
+import readline4k.read_request_await
+import readline4k.read_request_cancel
+import readline4k.read_request_poll
+
+/**
+ * A line being read in the background, started with [AbstractLineEditor.readLineAsync].
+ *
+ * The read runs on a native worker thread, so the calling thread stays free (e.g. to drive an
+ * event loop). Check for the result with [poll], block for it with [await], or stop the read
+ * with [cancel]. Every method can be called from any thread.
+ *
+ * Example:
+ * ```kotlin
+ * val request = editor.readLineAsync().getOrThrow()
+ * while (true) {
+ *     val line = request.poll() ?: run { processEvents(); continue }
+ *     println(line.getOrThrow())
+ *     break
+ * }
+ * request.close()
+ * ```
+ *
+ * Call [close] once the result is no longer needed.
+ */
+@OptIn(ExperimentalForeignApi::class)
+class ReadLineRequest internal constructor(
+    private val request: ReadRequestHandle,
+) : AutoCloseable {
+    internal var closed: Boolean = false
+        private set
+
+    /**
+     * Get the result of the read without blocking.
+     *
+     * @return null while the read is in progress, otherwise the line read (or the error, e.g.
+     * [LineEditorError.Code.Cancelled] if the read was cancelled).
+     */
+    fun poll(): Result<String>? = memScoped {
+        val done = alloc<BooleanVar>()
+        read_request_poll(request, done.ptr).toResult(done.value)
+    }
+
+    /**
+     * Wait until the read completes or [timeoutMillis] elapses.
+     *
+     * @param timeoutMillis how long to wait, or -1 to wait until the read completes.
+     * @return null if the timeout elapsed first, otherwise the result of the read as in [poll].
+     */
+    fun await(timeoutMillis: Int = -1): Result<String>? = memScoped {
+        val done = alloc<BooleanVar>()
+        read_request_await(request, timeoutMillis, done.ptr).toResult(done.value)
+    }
+
+    /**
+     * Cancel the read. The result of the read becomes a [LineEditorError.Code.Cancelled] failure.
+     *
+     * The restrictions of [AbstractLineEditor.cancelReadLine] apply.
+     *
+     * @return true if the read was cancelled, false if it had already completed.
+     */
+    fun cancel(): Result<Boolean> = memScoped {
+        val cancelled = alloc<BooleanVar>()
+        read_request_cancel(request, cancelled.ptr).toUnitResult().map { cancelled.value }
+    }
+
+    /**
+     * Releases the native request. A read still in progress is cancelled first, and this waits
+     * until it stopped (until it completes if it cannot be cancelled, see [cancel]).
+     * Calling any other method afterward fails with [LineEditorError.Code.Disposed].
+     */
+    override fun close() {
+        if (closed) return
+        // The read calls back into the editor, which must not release its callbacks while it runs.
+        cancel()
+        await()
+        free_read_request(request)
+        closed = true
+    }
+
+    private fun CPointer<ReadLineResult>?.toResult(done: Boolean): Result<String>? {
+        val result = toNullableStringResult()
+        return if (result.isSuccess && !done) null else result.map { it!! }
+    }
+}
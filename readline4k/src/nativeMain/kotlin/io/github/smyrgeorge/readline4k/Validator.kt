@@ -10,11 +10,26 @@ interface Validator {
      * Validates the given input line and position to determine its state.
      *
      * @param line the current input buffer to validate
-     * @param pos the caret position within the input line
+     * @param pos the caret position within the input line, as an index into [line] (0..line.length)
      * @return a Validation result indicating whether the input is valid, incomplete, or invalid
      */
     fun validate(line: String, pos: Int): Validation
 
+    /**
+     * Validates the given input line, knowing what triggered the validation.
+     *
+     * Override this to tell the cases apart, e.g. to return [Validation.Incomplete] (which inserts
+     * a newline at the cursor) only when Enter is pressed in the middle of a multi-line statement.
+     * Delegates to the two-argument [validate] by default.
+     *
+     * @param line the current input buffer to validate
+     * @param pos the caret position within the input line, as an index into [line] (0..line.length);
+     *   the end of the input unless [trigger] is [Trigger.ENTER]
+     * @param trigger what triggered the validation
+     * @return a Validation result indicating whether the input is valid, incomplete, or invalid
+     */
+    fun validate(line: String, pos: Int, trigger: Trigger): Validation = validate(line, pos)
+
     /**
      * Determines if the validate method should be invoked during typing.
      *
@@ -22,6 +37,18 @@ interface Validator {
      */
     fun validateWhileTyping(): Boolean = false
 
+    /**
+     * What triggered a validation.
+     */
+    enum class Trigger {
+        // IMPORTANT: Do not change the order of the triggers.
+        /** The user pressed Enter, and the command it runs (see [AbstractLineEditor.bindSequence]) accepts the line */
+        ENTER,
+
+        /** Any other accept command (e.g. a key bound to [Command.AcceptLine]) or validation while typing */
+        OTHER
+    }
+
     /**
      * Represents the result of a validation operation performed on user input.
      *
@@ -0,0 +1,101 @@
+package io.github.smyrgeorge.readline4k.impl
+
+import io.github.smyrgeorge.readline4k.AbstractLineEditor
+import io.github.smyrgeorge.readline4k.KeyEvent
+import io.github.smyrgeorge.readline4k.LineEditorConfig
+import kotlinx.cinterop.ExperimentalForeignApi
+import kotlinx.cinterop.memScoped
+import kotlinx.cinterop.toCValues
+import readline4k.editor_feed_input
+import readline4k.editor_feed_keys
+import readline4k.editor_take_output
+
+/**
+ * An [AbstractLineEditor] attached to a virtual terminal instead of the real one, for testing.
+ *
+ * Input is scripted with [feed] instead of typed, and everything the editor renders (prompt,
+ * highlighted input, hints, completion lists) is collected, either as raw ANSI output with
+ * [takeOutput] or interpreted into the off-screen [screen] with [render]. The full editing engine
+ * runs as usual, so completers, highlighters, hinters, validators and key bindings can be tested
+ * end to end without a TTY, e.g. in CI.
+ *
+ * Example:
+ * ```kotlin
+ * HeadlessLineEditor().use { editor ->
+ *     editor.setCompleter(MyCompleter())
+ *     editor.feed("sel\t\r")
+ *     assertEquals("select", editor.readLine().getOrThrow())
+ *     assertEquals("> select", editor.render().getOrThrow().line(0))
+ * }
+ * ```
+ *
+ * Notes:
+ * - Input can be fed before or during a read; a read waits for more input once the fed input
+ *   is consumed, so use [readLineWithTimeout] to guard against scripts that never accept the line.
+ * - The virtual terminal is a pseudo-terminal: only supported on Unix, otherwise the constructor
+ *   fails with [io.github.smyrgeorge.readline4k.LineEditorError.Code.Unsupported].
+ * - While the editor uses the terminal (e.g. during a read), the standard input and output of
+ *   the whole process are redirected to the virtual terminal, so output printed by other threads
+ *   meanwhile is captured as well. Only one headless editor can use the terminal at a time: a read
+ *   (or [clearScreen]) started while another one is reading fails with
+ *   [io.github.smyrgeorge.readline4k.LineEditorError.Code.Busy].
+ * - [LineEditorConfig.behavior] is ignored.
+ *
+ * @param linePrefix The prompt displayed before each input line. Defaults to "> ".
+ * @param config Editor configuration. Defaults to a new [LineEditorConfig].
+ * @property columns The width of the virtual terminal.
+ * @property rows The height of the virtual terminal.
+ */
+@OptIn(ExperimentalForeignApi::class)
+class HeadlessLineEditor(
+    linePrefix: String = "> ",
+    config: LineEditorConfig = LineEditorConfig(),
+    val columns: Int = 80,
+    val rows: Int = 24,
+) : AbstractLineEditor(linePrefix, config, columns to rows) {
+    /**
+     * Feed raw terminal [input], e.g. `"abc\r"` or escape sequences such as `"\u001B[A"` (Up).
+     */
+    @OptIn(ExperimentalUnsignedTypes::class)
+    fun feed(input: String): Result<Unit> {
+        val bytes = input.encodeToByteArray().asUByteArray()
+        return editor_feed_input(rl, bytes.toCValues(), bytes.size).toUnitResult()
+    }
+
+    /**
+     * Feed [keys] as typed on an xterm-compatible terminal.
+     *
+     * Fails with [io.github.smyrgeorge.readline4k.LineEditorError.Code.InvalidArgument] if a key
+     * has no such encoding (e.g. Ctrl-Enter, or Shift with a character other than a letter).
+     */
+    fun feed(keys: List<KeyEvent>): Result<Unit> = memScoped {
+        if (keys.isEmpty()) return Result.success(Unit)
+        editor_feed_keys(rl, keys.toCArray(this), keys.size).toUnitResult()
+    }
+
+    /**
+     * The off-screen terminal updated by [render].
+     */
+    val screen: VirtualScreen = VirtualScreen(columns, rows)
+
+    /**
+     * Returns the raw output rendered since the last call (or since the editor was created).
+     *
+     * The output taken is not written to [screen]; mixing [takeOutput] and [render] leaves the
+     * screen out of date.
+     */
+    fun takeOutput(): Result<String> = editor_take_output(rl).toStringResult()
+
+    /**
+     * Writes the output rendered since the last call to [screen], and returns it.
+     *
+     * Example:
+     * ```kotlin
+     * editor.setHighlighter(SimpleHighlighter())
+     * editor.feed("select 1\r")
+     * editor.readLine()
+     * assertEquals("[fg=2]> [/]select 1", editor.render().getOrThrow().snapshot(styles = true))
+     * ```
+     */
+    fun render(): Result<VirtualScreen> = takeOutput().map { output -> screen.also { it.write(output) } }
+}
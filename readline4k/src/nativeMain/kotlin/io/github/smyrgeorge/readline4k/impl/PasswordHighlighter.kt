@@ -9,6 +9,9 @@ import io.github.smyrgeorge.readline4k.Highlighter
  * representation of the current line with a sequence of '*' of the same length,
  * keeping the actual input intact in the editor's buffer.
  *
+ * The input still goes through the hinter, completer, and history of the editor; to read
+ * an actual password, prefer [io.github.smyrgeorge.readline4k.AbstractLineEditor.readPassword].
+ *
  * Behavior notes:
  * - The [highlight] method always returns a masked string regardless of the cursor position.
  * - The [highlightChar] method returns false for [Highlighter.CmdKind.MOVE_CURSOR] so that
@@ -0,0 +1,346 @@
+package io.github.smyrgeorge.readline4k.impl
+
+/**
+ * An in-memory terminal: a grid of styled cells that interprets the output written to it the way
+ * an xterm-compatible terminal would.
+ *
+ * Used with a [HeadlessLineEditor] (see [HeadlessLineEditor.render]) to assert what the user would
+ * actually see, e.g. in golden snapshot tests of highlighters, hints, and completion lists.
+ *
+ * Supported output: printable characters (with line wrapping and scrolling), carriage return,
+ * line feed, backspace, tab, cursor movement and positioning, erasing in line/display, cursor
+ * visibility, and SGR attributes (bold, dim, italic, underline, inverse, 16/256/RGB colors).
+ * Other control sequences are ignored. Each character occupies a single cell.
+ *
+ * Example:
+ * ```kotlin
+ * val screen = VirtualScreen(columns = 20, rows = 2)
+ * screen.write("\u001B[32m> \u001B[0mhello")
+ * screen.snapshot()              // "> hello"
+ * screen.snapshot(styles = true) // "[fg=2]> [/]hello"
+ * ```
+ *
+ * @property columns The width of the screen.
+ * @property rows The height of the screen.
+ */
+class VirtualScreen(
+    val columns: Int = 80,
+    val rows: Int = 24,
+) {
+    init {
+        require(columns > 0 && rows > 0) { "The screen must have at least one column and one row." }
+    }
+
+    /**
+     * A terminal color.
+     */
+    sealed interface Color {
+        /** One of the 256 palette colors; 0-7 are the standard colors and 8-15 their bright variants. */
+        data class Indexed(val index: Int) : Color {
+            override fun toString(): String = "$index"
+        }
+
+        /** A 24-bit color. */
+        data class Rgb(val red: Int, val green: Int, val blue: Int) : Color {
+            override fun toString(): String =
+                listOf(red, green, blue).joinToString("", prefix = "#") { it.toString(16).padStart(2, '0') }
+        }
+    }
+
+    /**
+     * The attributes a cell is rendered with; null colors are the terminal's defaults.
+     */
+    data class Style(
+        val foreground: Color? = null,
+        val background: Color? = null,
+        val bold: Boolean = false,
+        val dim: Boolean = false,
+        val italic: Boolean = false,
+        val underline: Boolean = false,
+        val inverse: Boolean = false,
+    ) {
+        override fun toString(): String = listOfNotNull(
+            foreground?.let { "fg=$it" },
+            background?.let { "bg=$it" },
+            "bold".takeIf { bold },
+            "dim".takeIf { dim },
+            "italic".takeIf { italic },
+            "underline".takeIf { underline },
+            "inverse".takeIf { inverse },
+        ).joinToString(",")
+
+        companion object {
+            val DEFAULT: Style = Style()
+        }
+    }
+
+    /**
+     * A single cell of the screen.
+     *
+     * @property text The character displayed in the cell (a surrogate pair for supplementary characters).
+     */
+    data class Cell(val text: String, val style: Style) {
+        companion object {
+            val BLANK: Cell = Cell(" ", Style.DEFAULT)
+        }
+    }
+
+    private val grid: Array<Array<Cell>> = Array(rows) { blankRow() }
+
+    /** The row of the cursor, 0 being the top row. */
+    var cursorRow: Int = 0
+        private set
+
+    /** The column of the cursor, 0 being the leftmost column. */
+    var cursorColumn: Int = 0
+        private set
+
+    /** Whether the cursor is shown. */
+    var cursorVisible: Boolean = true
+        private set
+
+    private var style: Style = Style.DEFAULT
+    private var pendingWrap: Boolean = false
+    private var savedCursor: Pair<Int, Int> = 0 to 0
+
+    private var state: State = State.GROUND
+    private val sequence: StringBuilder = StringBuilder()
+    private var highSurrogate: Char? = null
+
+    private enum class State { GROUND, ESCAPE, CHARSET, CSI, OSC, OSC_ESCAPE }
+
+    /**
+     * Returns the cell at [row] and [column].
+     */
+    fun cell(row: Int, column: Int): Cell = grid[row][column]
+
+    /**
+     * Returns the text of [row], without trailing blanks.
+     */
+    fun line(row: Int): String = grid[row].joinToString("") { it.text }.trimEnd()
+
+    /**
+     * Returns the text of all rows, without trailing blanks and trailing empty rows.
+     */
+    fun lines(): List<String> = (0 until rows).map(::line).dropLastWhile { it.isEmpty() }
+
+    /**
+     * Returns a textual snapshot of the screen, suitable for golden tests.
+     *
+     * With [styles], every change of style is marked inline: `[fg=1,bold]` starts a styled run
+     * (see [Style.toString]) and `[/]` returns to the default style.
+     */
+    fun snapshot(styles: Boolean = false): String {
+        if (!styles) return lines().joinToString("\n")
+        val last = (rows - 1 downTo 0).firstOrNull { row -> grid[row].any { it != Cell.BLANK } } ?: -1
+        return (0..last).joinToString("\n") { row ->
+            val cells = grid[row].dropLastWhile { it == Cell.BLANK }
+            buildString {
+                var current = Style.DEFAULT
+                cells.forEach { cell ->
+                    if (cell.style != current) {
+                        append(if (cell.style == Style.DEFAULT) "[/]" else "[${cell.style}]")
+                        current = cell.style
+                    }
+                    append(cell.text)
+                }
+                if (current != Style.DEFAULT) append("[/]")
+            }
+        }
+    }
+
+    /**
+     * Interprets [output] as written to the terminal.
+     */
+    fun write(output: String) {
+        output.forEach(::process)
+    }
+
+    /**
+     * Clears the screen and resets the cursor and the attributes.
+     */
+    fun reset() {
+        grid.indices.forEach { grid[it] = blankRow() }
+        cursorRow = 0
+        cursorColumn = 0
+        cursorVisible = true
+        style = Style.DEFAULT
+        pendingWrap = false
+        savedCursor = 0 to 0
+        state = State.GROUND
+        sequence.clear()
+        highSurrogate = null
+    }
+
+    private fun process(c: Char) {
+        when (state) {
+            State.GROUND -> ground(c)
+            State.ESCAPE -> escape(c)
+            State.CHARSET -> state = State.GROUND
+            State.CSI -> if (c in '@'..'~') {
+                csi(sequence.toString(), c)
+                state = State.GROUND
+            } else {
+                sequence.append(c)
+            }
+            State.OSC -> when (c) {
+                BEL -> state = State.GROUND
+                ESC -> state = State.OSC_ESCAPE
+                else -> Unit
+            }
+            State.OSC_ESCAPE -> state = if (c == '\\') State.GROUND else State.OSC
+        }
+    }
+
+    private fun ground(c: Char) {
+        when {
+            c == ESC -> state = State.ESCAPE
+            c == '\r' -> moveTo(cursorRow, 0)
+            c == '\n' -> lineFeed()
+            c == '\b' -> moveTo(cursorRow, cursorColumn - 1)
+            c == '\t' -> moveTo(cursorRow, (cursorColumn / TAB_STOP + 1) * TAB_STOP)
+            c < ' ' || c == '\u007F' -> Unit
+            c.isHighSurrogate() -> highSurrogate = c
+            else -> {
+                val text = highSurrogate?.takeIf { c.isLowSurrogate() }?.let { "$it$c" } ?: "$c"
+                highSurrogate = null
+                put(text)
+            }
+        }
+    }
+
+    private fun escape(c: Char) {
+        state = State.GROUND
+        when (c) {
+            '[' -> {
+                sequence.clear()
+                state = State.CSI
+            }
+            ']' -> state = State.OSC
+            '(', ')', '*', '+' -> state = State.CHARSET
+            '7' -> savedCursor = cursorRow to cursorColumn
+            '8' -> moveTo(savedCursor.first, savedCursor.second)
+            'c' -> reset()
+            else -> Unit
+        }
+    }
+
+    private fun csi(body: String, command: Char) {
+        val isPrivate = body.startsWith('?')
+        val params = body.removePrefix("?").split(';', ':').map { it.toIntOrNull() }
+        fun param(index: Int, default: Int): Int = params.getOrNull(index)?.takeIf { it > 0 } ?: default
+        when (command) {
+            'A' -> moveTo(cursorRow - param(0, 1), cursorColumn)
+            'B' -> moveTo(cursorRow + param(0, 1), cursorColumn)
+            'C' -> moveTo(cursorRow, cursorColumn + param(0, 1))
+            'D' -> moveTo(cursorRow, cursorColumn - param(0, 1))
+            'E' -> moveTo(cursorRow + param(0, 1), 0)
+            'F' -> moveTo(cursorRow - param(0, 1), 0)
+            'G' -> moveTo(cursorRow, param(0, 1) - 1)
+            'd' -> moveTo(param(0, 1) - 1, cursorColumn)
+            'H', 'f' -> moveTo(param(0, 1) - 1, param(1, 1) - 1)
+            'J' -> eraseInDisplay(params.firstOrNull() ?: 0)
+            'K' -> eraseInLine(cursorRow, params.firstOrNull() ?: 0)
+            'm' -> if (!isPrivate) selectGraphicRendition(params.map { it ?: 0 })
+            'h', 'l' -> if (isPrivate && params.contains(25)) cursorVisible = command == 'h'
+            's' -> savedCursor = cursorRow to cursorColumn
+            'u' -> moveTo(savedCursor.first, savedCursor.second)
+            else -> Unit
+        }
+    }
+
+    private fun selectGraphicRendition(params: List<Int>) {
+        var i = 0
+        while (i < params.size) {
+            when (val p = params[i]) {
+                0 -> style = Style.DEFAULT
+                1 -> style = style.copy(bold = true)
+                2 -> style = style.copy(dim = true)
+                3 -> style = style.copy(italic = true)
+                4 -> style = style.copy(underline = true)
+                7 -> style = style.copy(inverse = true)
+                22 -> style = style.copy(bold = false, dim = false)
+                23 -> style = style.copy(italic = false)
+                24 -> style = style.copy(underline = false)
+                27 -> style = style.copy(inverse = false)
+                in 30..37 -> style = style.copy(foreground = Color.Indexed(p - 30))
+                39 -> style = style.copy(foreground = null)
+                in 40..47 -> style = style.copy(background = Color.Indexed(p - 40))
+                49 -> style = style.copy(background = null)
+                in 90..97 -> style = style.copy(foreground = Color.Indexed(p - 90 + 8))
+                in 100..107 -> style = style.copy(background = Color.Indexed(p - 100 + 8))
+                38, 48 -> {
+                    val color = when (params.getOrNull(i + 1)) {
+                        5 -> params.getOrNull(i + 2)?.let { Color.Indexed(it) }.also { i += 2 }
+                        2 -> params.subList(i + 2, minOf(i + 5, params.size))
+                            .takeIf { it.size == 3 }
+                            ?.let { (r, g, b) -> Color.Rgb(r, g, b) }
+                            .also { i += 4 }
+                        else -> null
+                    }
+                    style = if (p == 38) style.copy(foreground = color) else style.copy(background = color)
+                }
+                else -> Unit
+            }
+            i++
+        }
+    }
+
+    private fun put(text: String) {
+        if (pendingWrap) {
+            pendingWrap = false
+            cursorColumn = 0
+            lineFeed()
+        }
+        grid[cursorRow][cursorColumn] = Cell(text, style)
+        if (cursorColumn == columns - 1) pendingWrap = true else cursorColumn++
+    }
+
+    private fun lineFeed() {
+        pendingWrap = false
+        if (cursorRow < rows - 1) {
+            cursorRow++
+        } else {
+            // Scroll up
+            for (row in 0 until rows - 1) grid[row] = grid[row + 1]
+            grid[rows - 1] = blankRow()
+        }
+    }
+
+    private fun moveTo(row: Int, column: Int) {
+        pendingWrap = false
+        cursorRow = row.coerceIn(0, rows - 1)
+        cursorColumn = column.coerceIn(0, columns - 1)
+    }
+
+    private fun eraseInLine(row: Int, mode: Int) {
+        val range = when (mode) {
+            0 -> cursorColumn until columns
+            1 -> 0..cursorColumn
+            else -> 0 until columns
+        }
+        range.forEach { grid[row][it] = Cell.BLANK }
+    }
+
+    private fun eraseInDisplay(mode: Int) {
+        when (mode) {
+            0 -> {
+                eraseInLine(cursorRow, 0)
+                (cursorRow + 1 until rows).forEach { grid[it] = blankRow() }
+            }
+            1 -> {
+                (0 until cursorRow).forEach { grid[it] = blankRow() }
+                eraseInLine(cursorRow, 1)
+            }
+            else -> grid.indices.forEach { grid[it] = blankRow() }
+        }
+    }
+
+    private fun blankRow(): Array<Cell> = Array(columns) { Cell.BLANK }
+
+    private companion object {
+        const val ESC = '\u001B'
+        const val BEL = '\u0007'
+        const val TAB_STOP = 8
+    }
+}
@@ -3,18 +3,27 @@
 package io.github.smyrgeorge.readline4k.impl
 
 import io.github.smyrgeorge.readline4k.AbstractLineEditor
+import io.github.smyrgeorge.readline4k.Command
+import io.github.smyrgeorge.readline4k.EventHandler
 import io.github.smyrgeorge.readline4k.Highlighter.CmdKind
+import io.github.smyrgeorge.readline4k.KeyEvent
 import io.github.smyrgeorge.readline4k.LineEditorConfig
 import io.github.smyrgeorge.readline4k.LineEditorConfig.CompletionType
 import io.github.smyrgeorge.readline4k.LineEditorError
+import io.github.smyrgeorge.readline4k.Validator.Trigger
 import io.github.smyrgeorge.readline4k.Validator.Validation
 import kotlinx.cinterop.*
-import platform.posix.strdup
+import readline4k.CompletionCandidate
+import readline4k.EditorCommand
 import readline4k.EditorConfig
+import readline4k.EditorKeyEvent
 import readline4k.ReadLineResult
 import readline4k.free_read_line_result
+import readline4k.readline4k_alloc_candidates
+import readline4k.readline4k_alloc_string
+import readline4k.readline4k_normalize_key_sequence
 
-internal fun LineEditorConfig.toCValue(): CValue<EditorConfig> = cValue<EditorConfig> {
+internal fun LineEditorConfig.toCValue(scope: AutofreeScope): CValue<EditorConfig> = cValue<EditorConfig> {
     max_history_size = this@toCValue.maxHistorySize
     history_duplicates = this@toCValue.historyDuplicates.ordinal
     history_ignore_space = this@toCValue.historyIgnoreSpace
@@ -33,6 +42,145 @@ internal fun LineEditorConfig.toCValue(): CValue<EditorConfig> = cValue<EditorCo
     enable_bracketed_paste = this@toCValue.enableBracketedPaste
     enable_synchronized_output = this@toCValue.enableSynchronizedOutput
     enable_signals = this@toCValue.enableSignals
+    history_backend = when (val backend = this@toCValue.historyBackend) {
+        LineEditorConfig.HistoryBackend.File -> 0
+        is LineEditorConfig.HistoryBackend.Sqlite -> 1.also {
+            val path = backend.path?.utf8Bytes() ?: return@also
+            history_path = path.getPointer(scope)
+            history_path_len = path.size
+        }
+        LineEditorConfig.HistoryBackend.Memory -> 2
+        LineEditorConfig.HistoryBackend.Disabled -> 3
+    }
+}
+
+internal fun EditorConfig.toLineEditorConfig(historyPath: String?): LineEditorConfig = LineEditorConfig(
+    maxHistorySize = max_history_size,
+    historyDuplicates = LineEditorConfig.HistoryDuplicates.entries[history_duplicates],
+    historyIgnoreSpace = history_ignore_space,
+    completionType = CompletionType.entries[completion_type],
+    completionShowAllIfAmbiguous = completion_show_all_if_ambiguous,
+    completionPromptLimit = completion_prompt_limit,
+    keySeqTimeout = key_seq_timeout.takeIf { it >= 0 },
+    editMode = LineEditorConfig.EditMode.entries[edit_mode],
+    autoAddHistory = auto_add_history,
+    bellStyle = LineEditorConfig.BellStyle.entries[bell_style],
+    colorMode = LineEditorConfig.ColorMode.entries[color_mode],
+    behavior = LineEditorConfig.Behavior.entries[behavior],
+    tabStop = tab_stop.toInt(),
+    indentSize = indent_size.toInt(),
+    checkCursorPosition = check_cursor_position,
+    enableBracketedPaste = enable_bracketed_paste,
+    enableSynchronizedOutput = enable_synchronized_output,
+    enableSignals = enable_signals,
+    historyBackend = when (history_backend) {
+        1 -> LineEditorConfig.HistoryBackend.Sqlite(historyPath)
+        2 -> LineEditorConfig.HistoryBackend.Memory
+        3 -> LineEditorConfig.HistoryBackend.Disabled
+        else -> LineEditorConfig.HistoryBackend.File
+    },
+)
+
+internal fun List<KeyEvent>.toCArray(scope: AutofreeScope): CPointer<EditorKeyEvent> =
+    scope.allocArray<EditorKeyEvent>(size) { i ->
+        val event = this@toCArray[i]
+        code = event.code.ordinal
+        ch = event.value.toUInt()
+        modifiers = event.modifiers.fold(0) { acc, m -> acc or (1 shl m.ordinal) }.toUByte()
+    }
+
+/**
+ * This key sequence as the native engine binds it, e.g. with `Ctrl-a` as `Ctrl-A`.
+ */
+internal fun List<KeyEvent>.normalized(): Result<List<KeyEvent>> = memScoped {
+    val out = allocArray<EditorKeyEvent>(size)
+    readline4k_normalize_key_sequence(toCArray(this), size, out).toUnitResult().map {
+        List(size) { i -> out[i].toKeyEvent() }
+    }
+}
+
+private fun EditorKeyEvent.toKeyEvent(): KeyEvent = KeyEvent(
+    code = KeyEvent.Code.entries[code],
+    value = ch.toInt(),
+    modifiers = KeyEvent.Modifier.entries.filter { modifiers.toInt() and (1 shl it.ordinal) != 0 }.toSet(),
+)
+
+internal fun Command.toCValue(scope: AutofreeScope): CValue<EditorCommand> = cValue<EditorCommand> {
+    fill(this@toCValue) { it.getPointer(scope) }
+}
+
+private fun EditorCommand.fill(cmd: Command, allocText: (CValues<UByteVar>) -> CPointer<UByteVar>?) {
+    repeat = 1u
+    kind = when (cmd) {
+        Command.Noop -> 0
+        Command.Abort -> 1
+        Command.AcceptLine -> 2
+        is Command.AcceptOrInsertLine -> 3.also { flag = cmd.acceptInTheMiddle }
+        Command.BeginningOfHistory -> 4
+        Command.EndOfHistory -> 5
+        Command.CapitalizeWord -> 6
+        Command.DowncaseWord -> 7
+        Command.UpcaseWord -> 8
+        Command.ClearScreen -> 9
+        Command.Complete -> 10
+        Command.CompleteBackward -> 11
+        Command.CompleteHint -> 12
+        Command.EndOfFile -> 13
+        Command.ForwardSearchHistory -> 14
+        Command.ReverseSearchHistory -> 15
+        Command.HistorySearchBackward -> 16
+        Command.HistorySearchForward -> 17
+        is Command.Insert -> 18.also {
+            val bytes = cmd.text.utf8Bytes()
+            text = allocText(bytes)
+            text_len = bytes.size
+            repeat = cmd.repeat.toUShort()
+        }
+
+        Command.Interrupt -> 19
+        is Command.Kill -> 20.also {
+            movement = cmd.movement.ordinal
+            repeat = cmd.repeat.toUShort()
+        }
+
+        is Command.Move -> 21.also {
+            movement = cmd.movement.ordinal
+            repeat = cmd.repeat.toUShort()
+        }
+
+        Command.NextHistory -> 22
+        Command.PreviousHistory -> 23
+        Command.Newline -> 24
+        Command.QuotedInsert -> 25
+        Command.Repaint -> 26
+        is Command.SelfInsert -> 27.also {
+            ch = cmd.c.code.toUInt()
+            repeat = cmd.repeat.toUShort()
+        }
+
+        Command.Suspend -> 28
+        Command.TransposeChars -> 29
+        is Command.TransposeWords -> 30.also { repeat = cmd.repeat.toUShort() }
+        is Command.Undo -> 31.also { repeat = cmd.repeat.toUShort() }
+        is Command.Yank -> 32.also {
+            repeat = cmd.repeat.toUShort()
+            flag = cmd.beforeCursor
+        }
+
+        Command.YankPop -> 33
+        is Command.Indent -> 34.also {
+            movement = cmd.movement.ordinal
+            repeat = cmd.repeat.toUShort()
+        }
+
+        is Command.Dedent -> 35.also {
+            movement = cmd.movement.ordinal
+            repeat = cmd.repeat.toUShort()
+        }
+
+        is Command.LineUpOrPreviousHistory -> 36.also { repeat = cmd.repeat.toUShort() }
+        is Command.LineDownOrNextHistory -> 37.also { repeat = cmd.repeat.toUShort() }
+    }
 }
 
 internal fun CPointer<ReadLineResult>?.toUnitResult(): Result<Unit> {
@@ -45,15 +193,23 @@ internal fun CPointer<ReadLineResult>?.toUnitResult(): Result<Unit> {
 internal fun CPointer<ReadLineResult>?.toStringResult(): Result<String> {
     return use { result ->
         if (result.isError()) Result.failure(result.toError())
-        else Result.success(result.result!!.toKString())
+        else Result.success(result.resultString()!!)
+    }
+}
+
+internal fun CPointer<ReadLineResult>?.toNullableStringResult(): Result<String?> {
+    return use { result ->
+        if (result.isError()) Result.failure(result.toError())
+        else Result.success(result.resultString())
     }
 }
 
 private fun ReadLineResult.isError(): Boolean = error >= 0
+private fun ReadLineResult.resultString(): String? = result?.readBytes(result_len)?.decodeToString()
 private fun ReadLineResult.toError(): LineEditorError {
-    val code = LineEditorError.Code.entries[error]
+    val code = LineEditorError.Code.of(error)
     val message = error_message?.toKString()
-    return LineEditorError(code, message)
+    return LineEditorError(code, message, os_error.takeIf { it != 0 })
 }
 
 private inline fun <T> CPointer<ReadLineResult>?.use(block: (ReadLineResult) -> T): T {
@@ -65,112 +221,221 @@ private inline fun <T> CPointer<ReadLineResult>?.use(block: (ReadLineResult) ->
     }
 }
 
+/**
+ * The UTF-8 encoding of this string, passed to the native side along with its [CValues.size].
+ */
+@OptIn(ExperimentalUnsignedTypes::class)
+internal fun String.utf8Bytes(): CValues<UByteVar> = encodeToByteArray().asUByteArray().toCValues()
+
+/**
+ * Decodes the UTF-8 string of [size] bytes at this pointer, replacing malformed input with U+FFFD.
+ */
+private fun CPointer<UByteVar>.decodeUtf8(size: Int): String =
+    if (size <= 0) "" else readBytes(size).decodeToString()
+
+/**
+ * Decodes the UTF-8 line of [size] bytes at this pointer, along with the byte offset [pos]
+ * converted to an index into the decoded line.
+ */
+private fun CPointer<UByteVar>.decodeUtf8(size: Int, pos: Int): Pair<String, Int> {
+    val bytes = if (size <= 0) ByteArray(0) else readBytes(size)
+    val index = bytes.decodeToString(0, pos.coerceIn(0, bytes.size)).length
+    return bytes.decodeToString() to index
+}
+
+/**
+ * Converts the byte offset [offset] into the UTF-8 encoding of this string to an index into it.
+ */
+internal fun String.indexOfUtf8Offset(offset: Int): Int {
+    val bytes = encodeToByteArray()
+    return bytes.decodeToString(0, offset.coerceIn(0, bytes.size)).length
+}
+
+/**
+ * Converts the index [index] into this string to a byte offset into its UTF-8 encoding.
+ */
+private fun String.utf8Offset(index: Int): Int = encodeToByteArray(0, index.coerceIn(0, length)).size
+
+/**
+ * Copies these bytes to a buffer owned by the library, for a callback to return.
+ */
+private fun CValues<UByteVar>.toLibraryString(): CPointer<UByteVar>? = readline4k_alloc_string(this, size)
+
+/**
+ * Copies this string to a buffer owned by the library, for a callback to return, writing its size to [outLen].
+ */
+private fun String.toLibraryString(outLen: CPointer<IntVar>): CPointer<UByteVar>? {
+    val bytes = utf8Bytes()
+    outLen.pointed.value = bytes.size
+    return bytes.toLibraryString()
+}
+
 private fun getHolder(holderPointer: COpaquePointer?): AbstractLineEditor.CallbacksHolder {
     require(holderPointer != null) { "The holderPointer must not be null!" }
     return holderPointer.asStableRef<AbstractLineEditor.CallbacksHolder>().get()
 }
 
+/**
+ * Runs the body of a callback, returning [failure] if it throws: an exception must not unwind
+ * into the native library, which would terminate the process.
+ */
+private inline fun <T> catching(failure: T, block: () -> T): T =
+    try {
+        block()
+    } catch (_: Throwable) {
+        failure
+    }
+
 internal fun completerCallback(
     holderPointer: COpaquePointer?,
-    line: CPointer<ByteVar>?,
+    line: CPointer<UByteVar>?,
+    lineLen: Int,
     pos: Int,
     outStart: CPointer<IntVar>?,
-): CPointer<ByteVar>? {
-    if (line == null || outStart == null) return null
+    outLen: CPointer<IntVar>?,
+): CPointer<CompletionCandidate>? = catching(null) {
+    if (line == null || outStart == null || outLen == null) return@catching null
     val holder = getHolder(holderPointer)
-    val completer = holder.completer ?: return null
-    val (start, items) = completer.complete(line.toKString(), pos)
-    outStart.pointed.value = start
-    val joined = items.joinToString("_*#*_")
-    // return malloc-allocated string for Rust to free via free()
-    return strdup(joined)?.reinterpret()
+    val completer = holder.completer ?: return@catching null
+    val (text, index) = line.decodeUtf8(lineLen, pos)
+    val (start, candidates) = completer.completeCandidates(text, index)
+    outStart.pointed.value = text.utf8Offset(start)
+    outLen.pointed.value = candidates.size
+    if (candidates.isEmpty()) return@catching null
+    // array (and strings) allocated by the library, which takes ownership of them
+    val array = readline4k_alloc_candidates(candidates.size) ?: return@catching null
+    candidates.forEachIndexed { i, candidate ->
+        with(array[i]) {
+            val replacementBytes = candidate.replacement.utf8Bytes()
+            replacement = replacementBytes.toLibraryString()
+            replacement_len = replacementBytes.size
+            val displayBytes = candidate.display.utf8Bytes()
+            display = displayBytes.toLibraryString()
+            display_len = displayBytes.size
+            candidate.description?.utf8Bytes()?.let { descriptionBytes ->
+                description = descriptionBytes.toLibraryString()
+                description_len = descriptionBytes.size
+            }
+        }
+    }
+    array
+}
+
+internal fun hinterCallback(
+    holderPointer: COpaquePointer?,
+    line: CPointer<UByteVar>?,
+    lineLen: Int,
+    pos: Int,
+    outLen: CPointer<IntVar>?,
+): CPointer<UByteVar>? = catching(null) {
+    if (line == null || outLen == null) return@catching null
+    val holder = getHolder(holderPointer)
+    val hinter = holder.hinter ?: return@catching null
+    val (text, index) = line.decodeUtf8(lineLen, pos)
+    val hint = hinter.hint(text, index) ?: return@catching null
+    // allocated by the library, which takes ownership of it
+    hint.toLibraryString(outLen)
 }
 
 internal fun highlighterCallback(
     holderPointer: COpaquePointer?,
-    line: CPointer<ByteVar>?,
+    line: CPointer<UByteVar>?,
+    lineLen: Int,
     pos: Int,
-): CPointer<ByteVar>? {
-    if (line == null) return null
+    outLen: CPointer<IntVar>?,
+): CPointer<UByteVar>? = catching(null) {
+    if (line == null || outLen == null) return@catching null
     val holder = getHolder(holderPointer)
-    val highlighter = holder.highlighter ?: return null
-    val highlighted = highlighter.highlight(line.toKString(), pos)
-    // return malloc-allocated string for Rust to free via free()
-    return strdup(highlighted)?.reinterpret()
+    val highlighter = holder.highlighter ?: return@catching null
+    val (text, index) = line.decodeUtf8(lineLen, pos)
+    val highlighted = highlighter.highlight(text, index)
+    // allocated by the library, which takes ownership of it
+    highlighted.toLibraryString(outLen)
 }
 
 internal fun hintHighlighterCallback(
     holderPointer: COpaquePointer?,
-    hint: CPointer<ByteVar>?,
-): CPointer<ByteVar>? {
-    if (hint == null) return null
+    hint: CPointer<UByteVar>?,
+    hintLen: Int,
+    outLen: CPointer<IntVar>?,
+): CPointer<UByteVar>? = catching(null) {
+    if (hint == null || outLen == null) return@catching null
     val holder = getHolder(holderPointer)
-    val highlighter = holder.highlighter ?: return null
-    val highlighted = highlighter.highlightHint(hint.toKString())
-    // return malloc-allocated string for Rust to free via free()
-    return strdup(highlighted)?.reinterpret()
+    val highlighter = holder.highlighter ?: return@catching null
+    val highlighted = highlighter.highlightHint(hint.decodeUtf8(hintLen))
+    // allocated by the library, which takes ownership of it
+    highlighted.toLibraryString(outLen)
 }
 
 internal fun promptHighlighterCallback(
     holderPointer: COpaquePointer?,
-    prompt: CPointer<ByteVar>?,
+    prompt: CPointer<UByteVar>?,
+    promptLen: Int,
     isDefault: Boolean,
-): CPointer<ByteVar>? {
-    if (prompt == null) return null
+    outLen: CPointer<IntVar>?,
+): CPointer<UByteVar>? = catching(null) {
+    if (prompt == null || outLen == null) return@catching null
     val holder = getHolder(holderPointer)
-    val highlighter = holder.highlighter ?: return null
-    val highlighted = highlighter.highlightPrompt(prompt.toKString(), isDefault)
-    // return malloc-allocated string for Rust to free via free()
-    return strdup(highlighted)?.reinterpret()
+    val highlighter = holder.highlighter ?: return@catching null
+    val highlighted = highlighter.highlightPrompt(prompt.decodeUtf8(promptLen), isDefault)
+    // allocated by the library, which takes ownership of it
+    highlighted.toLibraryString(outLen)
 }
 
 internal fun candidateHighlighterCallback(
     holderPointer: COpaquePointer?,
-    candidate: CPointer<ByteVar>?,
+    candidate: CPointer<UByteVar>?,
+    candidateLen: Int,
     completion: Int,
-): CPointer<ByteVar>? {
-    if (candidate == null) return null
-    require(holderPointer != null) { "The holderPointer must not be null!" }
-    val holder = holderPointer.asStableRef<AbstractLineEditor.CallbacksHolder>().get()
-    val highlighter = holder.highlighter ?: return null
-    val highlighted = highlighter.highlightCandidate(candidate.toKString(), CompletionType.entries[completion])
-    // return malloc-allocated string for Rust to free via free()
-    return strdup(highlighted)?.reinterpret()
+    outLen: CPointer<IntVar>?,
+): CPointer<UByteVar>? = catching(null) {
+    if (candidate == null || outLen == null) return@catching null
+    val holder = getHolder(holderPointer)
+    val highlighter = holder.highlighter ?: return@catching null
+    val highlighted =
+        highlighter.highlightCandidate(candidate.decodeUtf8(candidateLen), CompletionType.entries[completion])
+    // allocated by the library, which takes ownership of it
+    highlighted.toLibraryString(outLen)
 }
 
 internal fun charHighlighterCallback(
     holderPointer: COpaquePointer?,
-    line: CPointer<ByteVar>?,
+    line: CPointer<UByteVar>?,
+    lineLen: Int,
     pos: Int,
     kind: Int
-): Boolean {
-    if (line == null) return false
-    require(holderPointer != null) { "The holderPointer must not be null!" }
-    val holder = holderPointer.asStableRef<AbstractLineEditor.CallbacksHolder>().get()
-    val highlighter = holder.highlighter ?: return false
-    return highlighter.highlightChar(line.toKString(), pos, CmdKind.entries[kind])
+): Boolean = catching(false) {
+    if (line == null) return@catching false
+    val holder = getHolder(holderPointer)
+    val highlighter = holder.highlighter ?: return@catching false
+    val (text, index) = line.decodeUtf8(lineLen, pos)
+    highlighter.highlightChar(text, index, CmdKind.entries[kind])
 }
 
 internal fun validatorCallback(
     holderPointer: COpaquePointer?,
-    line: CPointer<ByteVar>?,
+    line: CPointer<UByteVar>?,
+    lineLen: Int,
     pos: Int,
-    outMessage: CPointer<CPointerVar<ByteVar>>?,
-): Int {
-    if (line == null) return 0 // treat as Valid
+    trigger: Int,
+    outMessage: CPointer<CPointerVar<UByteVar>>?,
+    outMessageLen: CPointer<IntVar>?,
+): Int = catching(1) { // an exception rejects the line as Invalid, keeping it for editing
+    if (line == null) return@catching 0 // treat as Valid
     val holder = getHolder(holderPointer)
-    val validator = holder.validator ?: return 0
-    return when (val res = validator.validate(line.toKString(), pos)) {
+    val validator = holder.validator ?: return@catching 0
+    val (text, index) = line.decodeUtf8(lineLen, pos)
+    when (val res = validator.validate(text, index, Trigger.entries[trigger])) {
         is Validation.Valid -> {
             val msg = res.message
-            val cstr = msg?.let { strdup(it) }?.reinterpret<ByteVar>()
+            val cstr = if (outMessageLen != null) msg?.toLibraryString(outMessageLen) else null
             if (outMessage != null) outMessage.pointed.value = cstr
             0
         }
 
         is Validation.Invalid -> {
             val msg = res.message
-            val cstr = msg?.let { strdup(it) }?.reinterpret<ByteVar>()
+            val cstr = if (outMessageLen != null) msg?.toLibraryString(outMessageLen) else null
             if (outMessage != null) outMessage.pointed.value = cstr
             1
         }
@@ -184,8 +449,31 @@ internal fun validatorCallback(
 
 internal fun validatorWhileTypingCallback(
     holderPointer: COpaquePointer?,
-): Boolean {
+): Boolean = catching(false) {
+    val holder = getHolder(holderPointer)
+    val validator = holder.validator ?: return@catching false
+    validator.validateWhileTyping()
+}
+
+internal fun eventHandlerCallback(
+    holderPointer: COpaquePointer?,
+    handlerId: Int,
+    line: CPointer<UByteVar>?,
+    lineLen: Int,
+    pos: Int,
+    repeat: Int,
+    positive: Boolean,
+    hint: CPointer<UByteVar>?,
+    hintLen: Int,
+    outCmd: CPointer<EditorCommand>?,
+): Boolean = catching(false) {
+    if (line == null || outCmd == null) return@catching false
     val holder = getHolder(holderPointer)
-    val validator = holder.validator ?: return false
-    return validator.validateWhileTyping()
+    val handler = holder.eventHandlers[handlerId] ?: return@catching false
+    val (text, index) = line.decodeUtf8(lineLen, pos)
+    val context = EventHandler.Context(text, index, repeat, positive, hint?.decodeUtf8(hintLen))
+    val cmd = handler.handle(context) ?: return@catching false
+    // text (if any) is allocated by the library, which takes ownership of it
+    outCmd.pointed.fill(cmd) { it.toLibraryString() }
+    true
 }
@@ -0,0 +1,18 @@
+package io.github.smyrgeorge.readline4k
+
+import kotlin.test.Test
+import kotlin.test.assertEquals
+
+class CompleterTest {
+    @Test
+    fun defaultCandidatesUseReplacementAsDisplay() {
+        val c = object : Completer {
+            override fun complete(line: String, pos: Int): Pair<Int, List<String>> =
+                2 to listOf("start", "status")
+        }
+        val (start, candidates) = c.completeCandidates("s st", 4)
+        assertEquals(2, start)
+        assertEquals(listOf(Completer.Candidate("start", "start"), Completer.Candidate("status", "status")), candidates)
+        assertEquals(null, candidates.first().description)
+    }
+}
@@ -0,0 +1,22 @@
+package io.github.smyrgeorge.readline4k
+
+import kotlin.test.Test
+import kotlin.test.assertEquals
+
+class LineEditorErrorTest {
+    @Test
+    fun codesAreResolvedByValue() {
+        LineEditorError.Code.entries.forEach { assertEquals(it, LineEditorError.Code.of(it.value)) }
+        assertEquals(LineEditorError.Code.Eof, LineEditorError.Code.of(0))
+        assertEquals(LineEditorError.Code.Interrupted, LineEditorError.Code.of(1))
+        assertEquals(LineEditorError.Code.Io, LineEditorError.Code.of(3))
+        assertEquals(LineEditorError.Code.InvalidArgument, LineEditorError.Code.of(9))
+        assertEquals(LineEditorError.Code.Panic, LineEditorError.Code.of(10))
+    }
+
+    @Test
+    fun unknownValuesFallBackToUnknown() {
+        assertEquals(LineEditorError.Code.Unknown, LineEditorError.Code.of(-1))
+        assertEquals(LineEditorError.Code.Unknown, LineEditorError.Code.of(1000))
+    }
+}
@@ -0,0 +1,414 @@
+package io.github.smyrgeorge.readline4k.impl
+
+import io.github.smyrgeorge.readline4k.AbstractLineEditor
+import io.github.smyrgeorge.readline4k.Command
+import io.github.smyrgeorge.readline4k.Completer
+import io.github.smyrgeorge.readline4k.EventHandler
+import io.github.smyrgeorge.readline4k.Hinter
+import io.github.smyrgeorge.readline4k.KeyEvent
+import io.github.smyrgeorge.readline4k.LineEditorConfig
+import io.github.smyrgeorge.readline4k.LineEditorError
+import io.github.smyrgeorge.readline4k.Validator
+import kotlinx.cinterop.ExperimentalForeignApi
+import kotlinx.cinterop.StableRef
+import kotlinx.io.files.Path
+import kotlinx.io.files.SystemFileSystem
+import kotlinx.io.files.SystemTemporaryDirectory
+import platform.posix.getpid
+import readline4k.editor_reset_helper
+import kotlin.test.Test
+import kotlin.test.assertEquals
+import kotlin.test.assertFailsWith
+import kotlin.test.assertFalse
+import kotlin.test.assertTrue
+
+class HeadlessLineEditorTest {
+    @Test
+    fun readsFedInput() {
+        HeadlessLineEditor(linePrefix = "> ").use { editor ->
+            editor.feed("hello\r").getOrThrow()
+            assertEquals("hello", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+            val output = editor.takeOutput().getOrThrow()
+            assertTrue(output.contains("> ") && output.contains("hello"), output)
+        }
+    }
+
+    @Test
+    fun appliesEditingKeys() {
+        HeadlessLineEditor().use { editor ->
+            editor.feed("world").getOrThrow()
+            editor.feed(listOf(KeyEvent.ctrl('A'))).getOrThrow()
+            editor.feed("hello ").getOrThrow()
+            editor.feed(listOf(KeyEvent.of(KeyEvent.Code.ENTER))).getOrThrow()
+            assertEquals("hello world", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+        }
+    }
+
+    @Test
+    fun readTimesOutWhenInputRunsOut() {
+        HeadlessLineEditor().use { editor ->
+            editor.feed("abc").getOrThrow()
+            val error = editor.readLineWithTimeout(100).exceptionOrNull() as LineEditorError
+            assertEquals(LineEditorError.Code.Timeout, error.code)
+        }
+    }
+
+    @Test
+    fun readTimesOutRightAway() {
+        HeadlessLineEditor().use { editor ->
+            // Times out before rustyline handles SIGINT, which must not kill the process.
+            repeat(10) {
+                val error = editor.readLineWithTimeout(0).exceptionOrNull() as LineEditorError
+                assertEquals(LineEditorError.Code.Timeout, error.code)
+            }
+        }
+    }
+
+    @Test
+    fun cancelsReadRightAfterStartingIt() {
+        HeadlessLineEditor().use { editor ->
+            repeat(10) {
+                editor.readLineAsync().getOrThrow().use { request ->
+                    assertTrue(request.cancel().getOrThrow())
+                    val error = request.await(TIMEOUT)!!.exceptionOrNull() as LineEditorError
+                    assertEquals(LineEditorError.Code.Cancelled, error.code)
+                }
+            }
+        }
+    }
+
+    @Test
+    fun cancelsReadInProgress() {
+        HeadlessLineEditor().use { editor ->
+            editor.readLineAsync().getOrThrow().use { request ->
+                var output = ""
+                while (!output.contains("> ")) output += editor.takeOutput().getOrThrow()
+                editor.feed("partial").getOrThrow()
+                assertTrue(editor.cancelReadLine().getOrThrow())
+                val error = request.await(TIMEOUT)!!.exceptionOrNull() as LineEditorError
+                assertEquals(LineEditorError.Code.Cancelled, error.code)
+            }
+            assertFalse(editor.cancelReadLine().getOrThrow())
+        }
+    }
+
+    @Test
+    fun closingRequestStopsTheRead() {
+        HeadlessLineEditor().use { editor ->
+            editor.readLineAsync().getOrThrow().close()
+            // The editor is free again, and the input goes to the next read.
+            editor.feed("next\r").getOrThrow()
+            assertEquals("next", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+        }
+    }
+
+    @Test
+    fun rejectsReadWhileAnotherHeadlessEditorReads() {
+        HeadlessLineEditor().use { reading ->
+            HeadlessLineEditor().use { other ->
+                reading.readLineAsync().getOrThrow().use {
+                    // The prompt is rendered once the read uses the terminal.
+                    var output = ""
+                    while (!output.contains("> ")) output += reading.takeOutput().getOrThrow()
+                    val error = other.readLineWithTimeout(TIMEOUT).exceptionOrNull() as LineEditorError
+                    assertEquals(LineEditorError.Code.Busy, error.code)
+                }
+            }
+        }
+    }
+
+    @Test
+    fun acceptsHintOfHinter() {
+        HeadlessLineEditor().use { editor ->
+            editor.setHinter(object : Hinter {
+                override fun hint(line: String, pos: Int): String? = if (line == "hel") "lo" else null
+            })
+            // Right at the end of the line accepts the hint.
+            editor.feed("hel").getOrThrow()
+            editor.feed(listOf(KeyEvent.of(KeyEvent.Code.RIGHT), KeyEvent.of(KeyEvent.Code.ENTER))).getOrThrow()
+            assertEquals("hello", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+        }
+    }
+
+    @Test
+    fun fallsBackToHistoryHints() {
+        HeadlessLineEditor().use { editor ->
+            val hinter = object : Hinter {
+                override fun hint(line: String, pos: Int): String? = null
+            }
+            editor.addHistoryEntry("history").getOrThrow()
+            val acceptHint = listOf(KeyEvent.of(KeyEvent.Code.RIGHT), KeyEvent.of(KeyEvent.Code.ENTER))
+            editor.setHinter(hinter, Hinter.HistoryHints.FALLBACK)
+            editor.feed("his").getOrThrow()
+            editor.feed(acceptHint).getOrThrow()
+            assertEquals("history", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+            editor.setHinter(hinter, Hinter.HistoryHints.DISABLED)
+            editor.feed("his").getOrThrow()
+            editor.feed(acceptHint).getOrThrow()
+            assertEquals("his", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+        }
+    }
+
+    @Test
+    fun placesCursorBetweenInitialText() {
+        HeadlessLineEditor().use { editor ->
+            editor.feed("X\r").getOrThrow()
+            assertEquals("abXcd", editor.readLineWithInitial("ab", "cd").getOrThrow())
+        }
+    }
+
+    @Test
+    fun printsAboveThePromptDuringRead() {
+        HeadlessLineEditor().use { editor ->
+            editor.createExternalPrinter().getOrThrow().use { printer ->
+                editor.readLineAsync().getOrThrow().use { request ->
+                    var output = ""
+                    while (!output.contains("> ")) output += editor.takeOutput().getOrThrow()
+                    editor.feed("typing").getOrThrow()
+                    printer.print("job finished").getOrThrow()
+                    while (!output.contains("job finished")) output += editor.takeOutput().getOrThrow()
+                    editor.feed("\r").getOrThrow()
+                    assertEquals("typing", request.await(TIMEOUT)!!.getOrThrow())
+                }
+            }
+        }
+    }
+
+    @Test
+    fun switchesEditModeAtRuntime() {
+        HeadlessLineEditor().use { editor ->
+            editor.setEditMode(LineEditorConfig.EditMode.VI).getOrThrow()
+            editor.setKeySeqTimeout(20).getOrThrow()
+            // Esc to normal mode, then insert at the beginning of the line
+            editor.feed("abc\u001B0iX\r").getOrThrow()
+            assertEquals("Xabc", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+        }
+    }
+
+    @Test
+    fun reportsEffectiveConfig() {
+        HeadlessLineEditor(config = LineEditorConfig(maxHistorySize = 42)).use { editor ->
+            editor.setEditMode(LineEditorConfig.EditMode.VI).getOrThrow()
+            val config = editor.effectiveConfig().getOrThrow()
+            assertEquals(42, config.maxHistorySize)
+            assertEquals(LineEditorConfig.EditMode.VI, config.editMode)
+            assertEquals(500, config.keySeqTimeout)
+        }
+    }
+
+    @Test
+    fun memoryHistoryCannotBeSaved() {
+        val config = LineEditorConfig(historyBackend = LineEditorConfig.HistoryBackend.Memory)
+        HeadlessLineEditor(config = config).use { editor ->
+            editor.addHistoryEntry("secret").getOrThrow()
+            assertEquals(listOf("secret"), editor.historyEntries().getOrThrow())
+            val path = tempPath("memory_history.txt")
+            val error = editor.saveHistory(path.toString()).exceptionOrNull() as LineEditorError
+            assertEquals(LineEditorError.Code.Unsupported, error.code)
+            assertFalse(SystemFileSystem.exists(path), "Nothing should be written to $path")
+        }
+    }
+
+    @Test
+    fun appendsHistoryOfConcurrentEditors() {
+        val path = tempPath("shared_history.txt").toString()
+        try {
+            HeadlessLineEditor().use { first ->
+                HeadlessLineEditor().use { second ->
+                    first.addHistoryEntry("from first").getOrThrow()
+                    first.appendHistory(path).getOrThrow()
+                    second.addHistoryEntry("from second").getOrThrow()
+                    second.appendHistory(path, merge = true).getOrThrow()
+                    assertEquals(listOf("from first", "from second"), second.historyEntries().getOrThrow())
+                    assertEquals(listOf("from first"), first.historyEntries().getOrThrow())
+                }
+            }
+            HeadlessLineEditor().use { editor ->
+                editor.loadHistory(path).getOrThrow()
+                assertEquals(listOf("from first", "from second"), editor.historyEntries().getOrThrow())
+            }
+        } finally {
+            SystemFileSystem.delete(Path(path), mustExist = false)
+        }
+    }
+
+    @Test
+    fun historyLengthFailsOnceDisposed() {
+        val editor = HeadlessLineEditor()
+        editor.addHistoryEntry("entry").getOrThrow()
+        assertEquals(1, editor.historyLength().getOrThrow())
+        editor.close()
+        val error = editor.historyLength().exceptionOrNull() as LineEditorError
+        assertEquals(LineEditorError.Code.Disposed, error.code)
+    }
+
+    @Test
+    fun staleHandlesFailOnceFreed() {
+        val editor = HeadlessLineEditor()
+        val printer = editor.createExternalPrinter().getOrThrow()
+        val request = editor.readLineAsync().getOrThrow()
+        printer.close()
+        request.close()
+        editor.close()
+        // A new editor may reuse the slot of the freed one, but not its handle.
+        HeadlessLineEditor().use { other ->
+            other.addHistoryEntry("entry").getOrThrow()
+            for (error in listOf(
+                printer.print("message").exceptionOrNull(),
+                request.poll()?.exceptionOrNull(),
+                editor.historyLength().exceptionOrNull(),
+            )) {
+                assertEquals(LineEditorError.Code.Disposed, (error as LineEditorError).code)
+            }
+            assertEquals(1, other.historyLength().getOrThrow())
+        }
+    }
+
+    @Test
+    fun setCompleterFailsOnceDisposed() {
+        val editor = HeadlessLineEditor()
+        editor.close()
+        val error = assertFailsWith<LineEditorError> {
+            editor.setCompleter(object : Completer {
+                override fun complete(line: String, pos: Int): Pair<Int, List<String>> = pos to emptyList()
+            })
+        }
+        assertEquals(LineEditorError.Code.Disposed, error.code)
+    }
+
+    @Test
+    fun readsMaskedPasswordWithoutHistory() {
+        HeadlessLineEditor(config = LineEditorConfig(autoAddHistory = true)).use { editor ->
+            editor.feed("secret\r").getOrThrow()
+            assertEquals("secret", editor.readPassword("Password: ", idleTimeoutMillis = TIMEOUT).getOrThrow())
+            val output = editor.takeOutput().getOrThrow()
+            assertTrue(output.contains("Password: ******") && !output.contains("secret"), output)
+            assertEquals(emptyList(), editor.historyEntries().getOrThrow())
+        }
+    }
+
+    @Test
+    fun passwordCannotBeYankedBack() {
+        HeadlessLineEditor().use { editor ->
+            // Ctrl-W kills the word typed so far.
+            editor.feed("secret\u0017pass\r").getOrThrow()
+            assertEquals("pass", editor.readPassword(idleTimeoutMillis = TIMEOUT).getOrThrow())
+            editor.feed(listOf(KeyEvent.ctrl('Y'), KeyEvent.of(KeyEvent.Code.ENTER))).getOrThrow()
+            assertEquals("", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+        }
+    }
+
+    @Test
+    fun refusesPasswordEchoedUnmasked() {
+        HeadlessLineEditor(config = LineEditorConfig(colorMode = LineEditorConfig.ColorMode.DISABLED)).use { editor ->
+            val error = editor.readPassword(idleTimeoutMillis = TIMEOUT).exceptionOrNull() as LineEditorError
+            assertEquals(LineEditorError.Code.Unsupported, error.code)
+        }
+    }
+
+    @Test
+    fun unsetsValidator() {
+        HeadlessLineEditor().use { editor ->
+            editor.setValidator(object : Validator {
+                override fun validate(line: String, pos: Int) = Validator.Validation.Invalid()
+            })
+            editor.unsetValidator()
+            editor.feed("done\r").getOrThrow()
+            assertEquals("done", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+        }
+    }
+
+    @Test
+    fun validatorReceivesCaretIndex() {
+        HeadlessLineEditor().use { editor ->
+            var caret = -1
+            editor.setValidator(object : Validator {
+                override fun validate(line: String, pos: Int) = Validator.Validation.Valid().also { caret = pos }
+            })
+            editor.feed("héllo").getOrThrow()
+            val left = KeyEvent.of(KeyEvent.Code.LEFT)
+            editor.feed(listOf(left, left, KeyEvent.of(KeyEvent.Code.ENTER))).getOrThrow()
+            assertEquals("héllo", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+            assertEquals(3, caret)
+        }
+    }
+
+    @Test
+    fun validatorReceivesCaretIndexWithEnterRebound() {
+        HeadlessLineEditor().use { editor ->
+            val enter = listOf(KeyEvent.of(KeyEvent.Code.ENTER))
+            assertFalse(editor.bindSequence(enter, Command.AcceptLine).getOrThrow())
+            assertTrue(editor.bindSequence(enter, object : EventHandler {
+                override fun handle(context: EventHandler.Context): Command? = null
+            }).getOrThrow())
+            var caret = -1
+            var accepted: Validator.Trigger? = null
+            editor.setValidator(object : Validator {
+                override fun validate(line: String, pos: Int) = Validator.Validation.Valid()
+                override fun validate(line: String, pos: Int, trigger: Validator.Trigger): Validator.Validation {
+                    caret = pos
+                    accepted = trigger
+                    return Validator.Validation.Valid()
+                }
+            })
+            editor.feed("abc").getOrThrow()
+            editor.feed(listOf(KeyEvent.of(KeyEvent.Code.LEFT)) + enter).getOrThrow()
+            assertEquals("abc", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+            assertEquals(2, caret)
+            assertEquals(Validator.Trigger.ENTER, accepted)
+            assertTrue(editor.unbindSequence(enter).getOrThrow())
+            assertFalse(editor.unbindSequence(enter).getOrThrow())
+        }
+    }
+
+    @Test
+    fun completesAfterMultiByteCharacters() {
+        HeadlessLineEditor().use { editor ->
+            editor.setCompleter(object : Completer {
+                override fun complete(line: String, pos: Int): Pair<Int, List<String>> {
+                    val start = line.lastIndexOf(' ', pos - 1) + 1
+                    val prefix = line.substring(start, pos)
+                    return start to listOf("wörld").filter { it.startsWith(prefix) }
+                }
+            })
+            editor.feed("héllo w\t\r").getOrThrow()
+            assertEquals("héllo wörld", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+        }
+    }
+
+    @Test
+    fun keepsNulCharacters() {
+        HeadlessLineEditor().use { editor ->
+            editor.feed("\r").getOrThrow()
+            assertEquals("a\u0000b", editor.readLineWithInitial("a\u0000", "b").getOrThrow())
+            editor.addHistoryEntry("c\u0000d").getOrThrow()
+            assertEquals("c\u0000d", editor.getHistoryEntry(0).getOrThrow())
+        }
+    }
+
+    @Test
+    @OptIn(ExperimentalForeignApi::class)
+    fun eventHandlerUsesHolderOfCurrentHelper() {
+        HeadlessLineEditor().use { editor ->
+            // Bind while another holder is installed, then switch back and free that holder.
+            val other = StableRef.create(AbstractLineEditor.CallbacksHolder())
+            editor_reset_helper(editor.rl, other.asCPointer()).toUnitResult().getOrThrow()
+            editor.bindSequence(listOf(KeyEvent.ctrl('T')), object : EventHandler {
+                override fun handle(context: EventHandler.Context): Command = Command.Insert("handled")
+            }).getOrThrow()
+            editor.resetHelper()
+            other.dispose()
+            editor.feed(listOf(KeyEvent.ctrl('T'), KeyEvent.of(KeyEvent.Code.ENTER))).getOrThrow()
+            assertEquals("handled", editor.readLineWithTimeout(TIMEOUT).getOrThrow())
+        }
+    }
+
+    private fun tempPath(name: String): Path =
+        Path(SystemTemporaryDirectory, "readline4k_test_${getpid()}_$name").also {
+            SystemFileSystem.delete(it, mustExist = false)
+        }
+
+    private companion object {
+        const val TIMEOUT = 5_000
+    }
+}
@@ -0,0 +1,22 @@
+package io.github.smyrgeorge.readline4k.impl
+
+import io.github.smyrgeorge.readline4k.KeyEvent
+import kotlin.test.Test
+import kotlin.test.assertEquals
+
+class KeyEventTest {
+    @Test
+    fun normalizesKeySequencesLikeTheEngine() {
+        val keys = listOf(
+            KeyEvent.ctrl('a'),
+            KeyEvent.char('B', KeyEvent.Modifier.SHIFT),
+            KeyEvent.of(KeyEvent.Code.TAB, KeyEvent.Modifier.SHIFT),
+        )
+        val expected = listOf(
+            KeyEvent.ctrl('A'),
+            KeyEvent.char('B'),
+            KeyEvent.of(KeyEvent.Code.BACK_TAB),
+        )
+        assertEquals(expected, keys.normalized().getOrThrow())
+    }
+}
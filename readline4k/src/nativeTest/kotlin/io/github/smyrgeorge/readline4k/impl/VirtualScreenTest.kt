@@ -0,0 +1,41 @@
+package io.github.smyrgeorge.readline4k.impl
+
+import io.github.smyrgeorge.readline4k.impl.VirtualScreen.Color
+import kotlin.test.Test
+import kotlin.test.assertEquals
+import kotlin.test.assertFalse
+
+class VirtualScreenTest {
+    @Test
+    fun rendersRefreshedLine() {
+        val screen = VirtualScreen(columns = 20, rows = 3)
+        // As rendered by the editor: prompt, input, then a refresh after moving the cursor.
+        screen.write("\r\u001B[K> \r\u001B[2Chello\r\u001B[K> hello world\r\u001B[8C")
+        assertEquals("> hello world", screen.snapshot())
+        assertEquals(0, screen.cursorRow)
+        assertEquals(8, screen.cursorColumn)
+    }
+
+    @Test
+    fun decodesAttributes() {
+        val screen = VirtualScreen(columns = 20, rows = 2)
+        screen.write("\u001B[32m> \u001B[0mab\u001B[1;38;5;200mc\u001B[22;48;2;255;0;16md\u001B[m")
+        assertEquals("[fg=2]> [/]ab[fg=200,bold]c[fg=200,bg=#ff0010]d[/]", screen.snapshot(styles = true))
+        assertEquals(Color.Indexed(2), screen.cell(0, 0).style.foreground)
+    }
+
+    @Test
+    fun wrapsAndScrolls() {
+        val screen = VirtualScreen(columns = 4, rows = 2)
+        screen.write("abcdefgh\r\nij")
+        assertEquals(listOf("efgh", "ij"), screen.lines())
+    }
+
+    @Test
+    fun erasesAndTracksCursorVisibility() {
+        val screen = VirtualScreen(columns = 10, rows = 3)
+        screen.write("one\r\ntwo\r\nthree\u001B[2;1H\u001B[J\u001B[?25l")
+        assertEquals(listOf("one"), screen.lines())
+        assertFalse(screen.cursorVisible)
+    }
+}
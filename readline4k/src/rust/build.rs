@@ -4,8 +4,10 @@ use std::env;
 
 fn main() {
     let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-    let mut config: cbindgen::Config = Default::default();
-    config.language = cbindgen::Language::C;
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        ..Default::default()
+    };
     cbindgen::generate_with_config(&crate_dir, config)
         .unwrap()
         .write_to_file("target/readline4k.h");
@@ -1,28 +1,80 @@
+use std::any::Any;
 use std::ffi::{c_char, c_int, c_void, CStr, CString};
+#[cfg(unix)]
+use std::fs::File;
+use std::io;
+#[cfg(unix)]
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+#[cfg(feature = "sqlite-history")]
+use std::path::PathBuf;
 use std::ptr::null_mut;
+use std::sync::atomic::{compiler_fence, AtomicBool, AtomicI32, AtomicPtr, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "sqlite-history")]
+use rusqlite::OptionalExtension;
 use rustyline::completion::{Completer, Pair};
 use rustyline::config::{
     Behavior, BellStyle, ColorMode, CompletionType, Config, Configurer, EditMode, HistoryDuplicates,
 };
 use rustyline::error::ReadlineError;
 use rustyline::highlight::{CmdKind, Highlighter};
-use rustyline::hint::HistoryHinter;
-use rustyline::history::FileHistory;
+use rustyline::hint::{Hinter, HistoryHinter};
+use rustyline::history::{FileHistory, History, MemHistory, SearchDirection, SearchResult};
+#[cfg(feature = "sqlite-history")]
+use rustyline::sqlite_history::SQLiteHistory;
 use rustyline::validate::{ValidationContext, ValidationResult, Validator};
-use rustyline::Editor;
-use rustyline_derive::{Helper, Hinter};
+use rustyline::{
+    Anchor, At, Cmd, ConditionalEventHandler, Editor, Event, EventContext, EventHandler,
+    ExternalPrinter, KeyCode, KeyEvent, Modifiers, Movement, RepeatCount, Word,
+};
+use rustyline_derive::Helper;
 
 const OK: c_int = -1;
 const ERROR_EOF: c_int = 0;
 const ERROR_INTERRUPTED: c_int = 1;
 const ERROR_UNKNOWN: c_int = 2;
+const ERROR_IO: c_int = 3;
+const ERROR_ERRNO: c_int = 4;
+const ERROR_WINDOW_RESIZED: c_int = 5;
+const ERROR_DECODE: c_int = 6;
+const ERROR_SIGNAL: c_int = 7;
+#[cfg(windows)]
+const ERROR_SYSTEM: c_int = 8;
+const ERROR_INVALID_ARGUMENT: c_int = 9;
+const ERROR_PANIC: c_int = 10;
+const ERROR_DISPOSED: c_int = 11;
+const ERROR_BUSY: c_int = 12;
+const ERROR_CANCELLED: c_int = 13;
+const ERROR_UNSUPPORTED: c_int = 14;
+const ERROR_TIMEOUT: c_int = 15;
+
+type RlEditor = Editor<CustomHelper, EditorHistory>;
+type SharedPrinter = Mutex<Box<dyn ExternalPrinter + Send>>;
+
+/// Opaque handle to an editor created by `new_editor_with_config`, 0 is never a valid handle.
+pub type EditorHandle = u64;
+/// Opaque handle to a printer created by `editor_create_external_printer`, 0 is never a valid handle.
+pub type PrinterHandle = u64;
+/// Opaque handle to a read started by `editor_read_line_async`, 0 is never a valid handle.
+pub type ReadRequestHandle = u64;
+
+static EDITORS: Mutex<Registry<EditorEntry>> = Mutex::new(Registry::new());
+static PRINTERS: Mutex<Registry<SharedPrinter>> = Mutex::new(Registry::new());
+static READ_REQUESTS: Mutex<Registry<ReadRequest>> = Mutex::new(Registry::new());
 
 #[repr(C)]
 pub struct ReadLineResult {
     pub error: c_int,
     pub error_message: *mut c_char,
-    pub result: *mut c_char,
+    pub os_error: c_int, // OS error number (errno) for ERROR_IO and ERROR_ERRNO, 0 if unavailable
+    pub result: *mut c_char, // UTF-8, NUL-terminated but may also contain NUL bytes
+    pub result_len: c_int, // in bytes, without the terminator
 }
 
 impl ReadLineResult {
@@ -38,7 +90,9 @@ impl Default for ReadLineResult {
         Self {
             error: OK,
             error_message: null_mut(),
+            os_error: 0,
             result: null_mut(),
+            result_len: 0,
         }
     }
 }
@@ -63,53 +117,175 @@ pub struct EditorConfig {
     pub enable_bracketed_paste: bool,
     pub enable_synchronized_output: bool,
     pub enable_signals: bool,
+    // 0 = FILE, 1 = SQLITE (requires the sqlite-history feature), 2 = MEMORY, 3 = NONE
+    pub history_backend: i32,
+    pub history_path: *const u8, // SQLite database, UTF-8, null or empty means in memory
+    pub history_path_len: c_int,
+}
+
+#[repr(C)]
+pub struct EditorKeyEvent {
+    // 0 = CHAR, 1 = BACKSPACE, 2 = BACK_TAB, 3 = DELETE, 4 = DOWN, 5 = END, 6 = ENTER, 7 = ESC,
+    // 8 = F, 9 = HOME, 10 = INSERT, 11 = LEFT, 12 = PAGE_DOWN, 13 = PAGE_UP, 14 = RIGHT,
+    // 15 = TAB, 16 = UP
+    pub code: i32,
+    pub ch: u32,       // unicode scalar for CHAR, function key number for F
+    pub modifiers: u8, // bitmask: 1 = SHIFT, 2 = ALT, 4 = CTRL
+}
+
+#[repr(C)]
+pub struct EditorCommand {
+    // 0 = NOOP, 1 = ABORT, 2 = ACCEPT_LINE, 3 = ACCEPT_OR_INSERT_LINE, 4 = BEGINNING_OF_HISTORY,
+    // 5 = END_OF_HISTORY, 6 = CAPITALIZE_WORD, 7 = DOWNCASE_WORD, 8 = UPCASE_WORD,
+    // 9 = CLEAR_SCREEN, 10 = COMPLETE, 11 = COMPLETE_BACKWARD, 12 = COMPLETE_HINT,
+    // 13 = END_OF_FILE, 14 = FORWARD_SEARCH_HISTORY, 15 = REVERSE_SEARCH_HISTORY,
+    // 16 = HISTORY_SEARCH_BACKWARD, 17 = HISTORY_SEARCH_FORWARD, 18 = INSERT, 19 = INTERRUPT,
+    // 20 = KILL, 21 = MOVE, 22 = NEXT_HISTORY, 23 = PREVIOUS_HISTORY, 24 = NEWLINE,
+    // 25 = QUOTED_INSERT, 26 = REPAINT, 27 = SELF_INSERT, 28 = SUSPEND, 29 = TRANSPOSE_CHARS,
+    // 30 = TRANSPOSE_WORDS, 31 = UNDO, 32 = YANK, 33 = YANK_POP, 34 = INDENT, 35 = DEDENT,
+    // 36 = LINE_UP_OR_PREVIOUS_HISTORY, 37 = LINE_DOWN_OR_NEXT_HISTORY
+    pub kind: i32,
+    // Used by KILL, MOVE, INDENT and DEDENT:
+    // 0 = WHOLE_LINE, 1 = BEGINNING_OF_LINE, 2 = END_OF_LINE, 3 = BACKWARD_WORD,
+    // 4 = FORWARD_WORD, 5 = BACKWARD_CHAR, 6 = FORWARD_CHAR, 7 = LINE_UP, 8 = LINE_DOWN,
+    // 9 = WHOLE_BUFFER, 10 = BEGINNING_OF_BUFFER, 11 = END_OF_BUFFER, 12 = FIRST_PRINT
+    pub movement: i32,
+    pub repeat: u16,
+    pub ch: u32, // SELF_INSERT
+    /// INSERT, UTF-8. Written by an `EventHandlerCb`, it must be allocated with
+    /// `readline4k_alloc_string` and the library takes ownership of it; passed to
+    /// `editor_bind_sequence`, it is only borrowed for the call.
+    pub text: *const u8,
+    pub text_len: c_int, // in bytes
+    pub flag: bool,      // ACCEPT_OR_INSERT_LINE: accept in the middle, YANK: before cursor
+}
+
+#[repr(C)]
+pub struct CompletionCandidate {
+    pub display: *mut u8, // null means same as replacement
+    pub display_len: c_int,
+    pub replacement: *mut u8, // the text inserted into the line
+    pub replacement_len: c_int,
+    pub description: *mut u8, // optional, shown after the display text
+    pub description_len: c_int,
 }
 
+/// Completes the word at `pos` in `line`. Returns the candidates, null if there are none,
+/// writing their number to `out_len` and the start of the text they replace to `out_start`.
+///
+/// The array must be allocated with `readline4k_alloc_candidates(*out_len)` and its strings with
+/// `readline4k_alloc_string`; the library takes ownership of all of them.
+///
+/// Strings are passed to this and the other callbacks as UTF-8 buffers with their length in
+/// bytes, only borrowed for the call; positions are byte offsets. Strings returned by callbacks
+/// must be allocated with `readline4k_alloc_string`, the library takes ownership of them and
+/// replaces invalid UTF-8 with U+FFFD.
 type CompleterCallCb = extern "C" fn(
     k_callback_holder: *mut c_void,
-    line: *const c_char,
+    line: *const u8,
+    line_len: c_int,
     pos: c_int,
     out_start: *mut c_int,
-) -> *mut c_char;
+    out_len: *mut c_int,
+) -> *mut CompletionCandidate;
+
+/// Returns the hint shown after the cursor, null for none, writing its length to `out_len`.
+/// The hint must be allocated with `readline4k_alloc_string`, the library takes ownership of it.
+type HinterCb = extern "C" fn(
+    k_callback_holder: *mut c_void,
+    line: *const u8,
+    line_len: c_int,
+    pos: c_int,
+    out_len: *mut c_int,
+) -> *mut u8;
 
-type HighlighterCb =
-    extern "C" fn(k_callback_holder: *mut c_void, line: *const c_char, pos: c_int) -> *mut c_char;
+/// Returns `line` styled for display, null to show it as is, writing its length to `out_len`.
+/// The result must be allocated with `readline4k_alloc_string`, the library takes ownership of it.
+type HighlighterCb = extern "C" fn(
+    k_callback_holder: *mut c_void,
+    line: *const u8,
+    line_len: c_int,
+    pos: c_int,
+    out_len: *mut c_int,
+) -> *mut u8;
 
-type HintHighlighterCb =
-    extern "C" fn(k_callback_holder: *mut c_void, hint: *const c_char) -> *mut c_char;
+/// Returns `hint` styled for display, null to show it as is. Allocated like [HighlighterCb].
+type HintHighlighterCb = extern "C" fn(
+    k_callback_holder: *mut c_void,
+    hint: *const u8,
+    hint_len: c_int,
+    out_len: *mut c_int,
+) -> *mut u8;
 
+/// Returns `prompt` styled for display, null to show it as is. Allocated like [HighlighterCb].
 type PromptHighlighterCb = extern "C" fn(
     k_callback_holder: *mut c_void,
-    prompt: *const c_char,
+    prompt: *const u8,
+    prompt_len: c_int,
     is_default: bool,
-) -> *mut c_char;
+    out_len: *mut c_int,
+) -> *mut u8;
 
+/// Returns a completion `candidate` styled for display, null to show it as is. Allocated like
+/// [HighlighterCb].
 type CandidateHighlighterCb = extern "C" fn(
     k_callback_holder: *mut c_void,
-    candidate: *const c_char,
+    candidate: *const u8,
+    candidate_len: c_int,
     completion: c_int,
-) -> *mut c_char;
+    out_len: *mut c_int,
+) -> *mut u8;
 
+/// Returns whether `line` must be highlighted again after a command of `kind` (0 = MOVE_CURSOR,
+/// 1 = OTHER, 2 = FORCED_REFRESH) left the cursor at `pos`. `line` is only borrowed for the call,
+/// and nothing is returned for the library to free.
 type CharHighlighterCb = extern "C" fn(
     k_callback_holder: *mut c_void,
-    line: *const c_char,
+    line: *const u8,
+    line_len: c_int,
     pos: c_int,
     kind: c_int,
 ) -> bool;
 
+/// Validates `line` and optionally writes a message to `out_message`, with its length to
+/// `out_message_len`. The message must be allocated with `readline4k_alloc_string`, the library
+/// takes ownership of it whatever the result.
+///
+/// `pos` is the cursor position when `trigger` is ENTER. Otherwise (e.g. when the line is
+/// accepted with Ctrl-J, or by a command bound to another key) it is the end of `line`.
 type ValidatorCb = extern "C" fn(
     k_callback_holder: *mut c_void,
-    line: *const c_char,
+    line: *const u8,
+    line_len: c_int,
     pos: c_int,
-    out_message: *mut *mut c_char,
+    trigger: c_int, // 0 = ENTER, 1 = OTHER
+    out_message: *mut *mut u8,
+    out_message_len: *mut c_int,
 ) -> c_int; // 0=Valid,1=Invalid,2=Incomplete
 
 type ValidatorWhileTypingCb = extern "C" fn(k_callback_holder: *mut c_void) -> bool;
 
-#[derive(Helper, Hinter)]
+/// Picks the command run for a bound key sequence, written to `out_cmd`. `hint` is null if
+/// there is none. The `text` of the command must be allocated with `readline4k_alloc_string`,
+/// the library takes ownership of it even if the callback returns false.
+type EventHandlerCb = extern "C" fn(
+    k_callback_holder: *mut c_void,
+    handler_id: c_int,
+    line: *const u8,
+    line_len: c_int,
+    pos: c_int,
+    repeat: c_int,
+    positive: bool,
+    hint: *const u8,
+    hint_len: c_int,
+    out_cmd: *mut EditorCommand,
+) -> bool; // true = execute out_cmd, false = fall back to the default command
+
+#[derive(Helper)]
 pub struct CustomHelper {
-    #[rustyline(Hinter)]
     hinter: HistoryHinter,
+    hinter_cb: Option<HinterCb>,
+    history_hints: c_int, // 0 = DISABLED, 1 = FALLBACK, 2 = PREFERRED
     completer_cb: Option<CompleterCallCb>,
     highlighter_cb: Option<HighlighterCb>,
     hint_highlighter_cb: Option<HintHighlighterCb>,
@@ -118,13 +294,23 @@ pub struct CustomHelper {
     char_highlighter_cb: Option<CharHighlighterCb>,
     validator_cb: Option<ValidatorCb>,
     validator_while_typing_cb: Option<ValidatorWhileTypingCb>,
+    accept_state: Arc<AcceptState>,
+    pending_read: Arc<SharedPendingRead>, // armed by the first hint of a read, see [ReadState]
     k_callback_holder: *mut c_void,
+    handler_holder: Arc<AtomicPtr<c_void>>, // read by the bound event handlers when they fire
+    mask: Option<char>, // set while reading a password, see `editor_read_password`
 }
 
+// Editors live in the global registry; the callbacks and the Kotlin holder are only ever
+// used from the thread that drives the editor.
+unsafe impl Send for CustomHelper {}
+
 impl Default for CustomHelper {
     fn default() -> Self {
         Self {
             hinter: HistoryHinter {},
+            hinter_cb: Default::default(),
+            history_hints: Default::default(),
             completer_cb: Default::default(),
             highlighter_cb: Default::default(),
             hint_highlighter_cb: Default::default(),
@@ -133,8 +319,178 @@ impl Default for CustomHelper {
             char_highlighter_cb: Default::default(),
             validator_cb: Default::default(),
             validator_while_typing_cb: Default::default(),
+            accept_state: Default::default(),
+            pending_read: Default::default(),
             k_callback_holder: Default::default(),
+            handler_holder: Default::default(),
+            mask: None,
+        }
+    }
+}
+
+/// The line and cursor position at the moment Enter was typed.
+///
+/// `ValidationContext` only exposes the input, so the position is recorded by [AcceptTracker]
+/// right before the accept command runs and consumed by the validator.
+#[derive(Default)]
+struct AcceptState {
+    last: Mutex<Option<(String, usize)>>,
+}
+
+impl AcceptState {
+    fn record(&self, line: &str, pos: usize) {
+        if let Ok(mut last) = self.last.lock() {
+            *last = Some((line.to_string(), pos));
+        }
+    }
+
+    /// Returns the recorded position if it was recorded for `line`.
+    fn take(&self, line: &str) -> Option<usize> {
+        let last = self.last.lock().ok()?.take();
+        last.filter(|(l, pos)| l == line && line.is_char_boundary(*pos))
+            .map(|(_, pos)| pos)
+    }
+}
+
+/// The time of the last key typed during the pending read, see `editor_read_line_with_timeout`.
+#[derive(Default)]
+struct KeyActivity {
+    last: Mutex<Option<Instant>>,
+}
+
+impl KeyActivity {
+    fn touch(&self) {
+        *self.last.lock().unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+    }
+
+    fn reset(&self) {
+        *self.last.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    fn last(&self) -> Option<Instant> {
+        *self.last.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Bound to any key without a binding of its own: marks key activity and falls back to the default command.
+struct ActivityTracker {
+    activity: Arc<KeyActivity>,
+}
+
+impl ConditionalEventHandler for ActivityTracker {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        _ctx: &EventContext,
+    ) -> Option<Cmd> {
+        self.activity.touch();
+        None
+    }
+}
+
+/// A key sequence bound to a fixed command, marking key activity like the unbound keys.
+struct CommandBinding {
+    cmd: Cmd,
+    activity: Arc<KeyActivity>,
+}
+
+impl ConditionalEventHandler for CommandBinding {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        _ctx: &EventContext,
+    ) -> Option<Cmd> {
+        self.activity.touch();
+        Some(self.cmd.clone())
+    }
+}
+
+/// Bound to Enter: runs the binding of the user if any (or falls back to the default command),
+/// recording the cursor position if the line is accepted.
+struct AcceptTracker {
+    state: Arc<AcceptState>,
+    activity: Arc<KeyActivity>,
+    binding: Option<Box<dyn ConditionalEventHandler>>,
+}
+
+impl ConditionalEventHandler for AcceptTracker {
+    fn handle(
+        &self,
+        evt: &Event,
+        n: RepeatCount,
+        positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        self.activity.touch();
+        let cmd = self
+            .binding
+            .as_ref()
+            .and_then(|binding| binding.handle(evt, n, positive, ctx));
+        if matches!(
+            cmd,
+            None | Some(Cmd::AcceptLine | Cmd::AcceptOrInsertLine { .. })
+        ) {
+            self.state.record(ctx.line(), ctx.pos());
         }
+        cmd
+    }
+}
+
+struct CallbackEventHandler {
+    cb: EventHandlerCb,
+    handler_id: c_int,
+    k_callback_holder: Arc<AtomicPtr<c_void>>,
+    activity: Arc<KeyActivity>,
+}
+
+// The callback is only ever invoked from the thread that drives `readline`.
+unsafe impl Send for CallbackEventHandler {}
+unsafe impl Sync for CallbackEventHandler {}
+
+impl ConditionalEventHandler for CallbackEventHandler {
+    fn handle(
+        &self,
+        _evt: &Event,
+        n: RepeatCount,
+        positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        self.activity.touch();
+        let line = ctx.line();
+        let hint = ctx.hint_text();
+        let mut out = EditorCommand {
+            kind: 0,
+            movement: 0,
+            repeat: 1,
+            ch: 0,
+            text: std::ptr::null(),
+            text_len: 0,
+            flag: false,
+        };
+        let handled = (self.cb)(
+            self.k_callback_holder.load(Ordering::Acquire),
+            self.handler_id,
+            line.as_ptr(),
+            line.len() as c_int,
+            ctx.pos() as c_int,
+            n as c_int,
+            positive,
+            hint.map_or(std::ptr::null(), str::as_ptr),
+            hint.map_or(0, |hint| hint.len() as c_int),
+            &mut out as *mut EditorCommand,
+        );
+        // A command that cannot be mapped falls back to the default action.
+        let cmd = if handled {
+            map_command(&out).ok()
+        } else {
+            None
+        };
+        take_string(out.text as *mut u8, out.text_len);
+        cmd
     }
 }
 
@@ -148,53 +504,108 @@ impl Completer for CustomHelper {
         _ctx: &rustyline::Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Pair>)> {
         if let Some(cb) = self.completer_cb {
-            let c_line = CString::new(line).unwrap();
             let mut start: c_int = pos as c_int;
+            let mut len: c_int = 0;
             let ptr = cb(
                 self.k_callback_holder,
-                c_line.as_ptr(),
+                line.as_ptr(),
+                line.len() as c_int,
                 pos as c_int,
                 &mut start as *mut c_int,
+                &mut len as *mut c_int,
             );
+            let start = completion_start(line, pos, start);
             if ptr.is_null() {
-                return Ok((start as usize, Vec::new()));
+                return Ok((start, Vec::new()));
             }
-            let items_str = unsafe {
-                CStr::from_ptr(ptr as *const c_char)
-                    .to_string_lossy()
-                    .into_owned()
-            };
-            unsafe { free(ptr as *mut c_void) };
-            let candidates: Vec<Pair> = items_str
-                .split("_*#*_")
-                .filter(|s| !s.is_empty())
-                .map(|s| Pair {
-                    display: s.to_string(),
-                    replacement: s.to_string(),
+            // Allocated by `readline4k_alloc_candidates`, with `len` entries.
+            let items = std::ptr::slice_from_raw_parts_mut(ptr, len.max(0) as usize);
+            let items = unsafe { Box::from_raw(items) };
+            let candidates: Vec<Pair> = items
+                .iter()
+                .filter_map(|item| {
+                    let replacement = take_string(item.replacement, item.replacement_len);
+                    let display = take_string(item.display, item.display_len);
+                    let description = take_string(item.description, item.description_len);
+                    let replacement = replacement.filter(|s| !s.is_empty())?;
+                    let display = display.unwrap_or_else(|| replacement.clone());
+                    let display = match description {
+                        Some(description) => format!("{}  -- {}", display, description),
+                        None => display,
+                    };
+                    Some(Pair {
+                        display,
+                        replacement,
+                    })
                 })
                 .collect();
-            Ok((start as usize, candidates))
+            Ok((start, candidates))
         } else {
             Ok((pos, Vec::new()))
         }
     }
 }
 
+/// Checks the `start` returned by a completer: rustyline slices `line` from it to `pos`, so
+/// anything but a char boundary up to `pos` falls back to `pos`.
+fn completion_start(line: &str, pos: usize, start: c_int) -> usize {
+    usize::try_from(start)
+        .ok()
+        .filter(|&start| start <= pos && line.is_char_boundary(start))
+        .unwrap_or(pos)
+}
+
+impl Hinter for CustomHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, ctx: &rustyline::Context<'_>) -> Option<String> {
+        // rustyline first asks for a hint right after installing its SIGINT handler.
+        self.pending_read.arm();
+        if let Some(cb) = self.hinter_cb {
+            let callback_hint = || {
+                let mut len: c_int = 0;
+                let ptr = cb(
+                    self.k_callback_holder,
+                    line.as_ptr(),
+                    line.len() as c_int,
+                    pos as c_int,
+                    &mut len as *mut c_int,
+                );
+                take_string(ptr, len)
+            };
+            match self.history_hints {
+                0 => callback_hint(),
+                1 => callback_hint().or_else(|| self.hinter.hint(line, pos, ctx)),
+                2 => self.hinter.hint(line, pos, ctx).or_else(callback_hint),
+                // Validated by `editor_set_hinter`.
+                _ => callback_hint(),
+            }
+        } else {
+            self.hinter.hint(line, pos, ctx)
+        }
+    }
+}
+
 impl Highlighter for CustomHelper {
     fn highlight<'h>(&self, line: &'h str, pos: usize) -> std::borrow::Cow<'h, str> {
+        if let Some(mask) = self.mask {
+            return std::borrow::Cow::Owned(
+                std::iter::repeat_n(mask, line.chars().count()).collect(),
+            );
+        }
         if let Some(cb) = self.highlighter_cb {
-            let c_line = CString::new(line).unwrap();
-            let ptr = cb(self.k_callback_holder, c_line.as_ptr(), pos as c_int);
-            if ptr.is_null() {
-                return std::borrow::Cow::Borrowed(line);
+            let mut len: c_int = 0;
+            let ptr = cb(
+                self.k_callback_holder,
+                line.as_ptr(),
+                line.len() as c_int,
+                pos as c_int,
+                &mut len as *mut c_int,
+            );
+            match take_string(ptr, len) {
+                Some(highlighted) => highlighted.into(),
+                None => std::borrow::Cow::Borrowed(line),
             }
-            let owned = unsafe {
-                CStr::from_ptr(ptr as *const c_char)
-                    .to_string_lossy()
-                    .into_owned()
-            };
-            unsafe { free(ptr as *mut c_void) };
-            owned.into()
         } else {
             std::borrow::Cow::Borrowed(line)
         }
@@ -202,18 +613,17 @@ impl Highlighter for CustomHelper {
 
     fn highlight_hint<'h>(&self, hint: &'h str) -> std::borrow::Cow<'h, str> {
         if let Some(cb) = self.hint_highlighter_cb {
-            let c_hint = CString::new(hint).unwrap();
-            let ptr = cb(self.k_callback_holder, c_hint.as_ptr());
-            if ptr.is_null() {
-                return std::borrow::Cow::Borrowed(hint);
+            let mut len: c_int = 0;
+            let ptr = cb(
+                self.k_callback_holder,
+                hint.as_ptr(),
+                hint.len() as c_int,
+                &mut len as *mut c_int,
+            );
+            match take_string(ptr, len) {
+                Some(highlighted) => highlighted.into(),
+                None => std::borrow::Cow::Borrowed(hint),
             }
-            let owned = unsafe {
-                CStr::from_ptr(ptr as *const c_char)
-                    .to_string_lossy()
-                    .into_owned()
-            };
-            unsafe { free(ptr as *mut c_void) };
-            owned.into()
         } else {
             std::borrow::Cow::Borrowed(hint)
         }
@@ -225,27 +635,23 @@ impl Highlighter for CustomHelper {
         completion: CompletionType,
     ) -> std::borrow::Cow<'c, str> {
         if let Some(cb) = self.candidate_highlighter_cb {
-            let c_candidate = CString::new(candidate).unwrap();
             let completion_code: c_int = match completion {
                 CompletionType::Circular => 0,
                 CompletionType::List => 1,
                 _ => 0,
             };
+            let mut len: c_int = 0;
             let ptr = cb(
                 self.k_callback_holder,
-                c_candidate.as_ptr(),
+                candidate.as_ptr(),
+                candidate.len() as c_int,
                 completion_code,
+                &mut len as *mut c_int,
             );
-            if ptr.is_null() {
-                return std::borrow::Cow::Borrowed(candidate);
+            match take_string(ptr, len) {
+                Some(highlighted) => highlighted.into(),
+                None => std::borrow::Cow::Borrowed(candidate),
             }
-            let owned = unsafe {
-                CStr::from_ptr(ptr as *const c_char)
-                    .to_string_lossy()
-                    .into_owned()
-            };
-            unsafe { free(ptr as *mut c_void) };
-            owned.into()
         } else {
             std::borrow::Cow::Borrowed(candidate)
         }
@@ -257,29 +663,32 @@ impl Highlighter for CustomHelper {
         is_default: bool,
     ) -> std::borrow::Cow<'b, str> {
         if let Some(cb) = self.prompt_highlighter_cb {
-            let c_prompt = CString::new(prompt).unwrap();
-            let ptr = cb(self.k_callback_holder, c_prompt.as_ptr(), is_default);
-            if ptr.is_null() {
-                return std::borrow::Cow::Owned(prompt.to_string());
+            let mut len: c_int = 0;
+            let ptr = cb(
+                self.k_callback_holder,
+                prompt.as_ptr(),
+                prompt.len() as c_int,
+                is_default,
+                &mut len as *mut c_int,
+            );
+            match take_string(ptr, len) {
+                Some(highlighted) => highlighted.into(),
+                None => std::borrow::Cow::Owned(prompt.to_string()),
             }
-            let owned = unsafe {
-                CStr::from_ptr(ptr as *const c_char)
-                    .to_string_lossy()
-                    .into_owned()
-            };
-            unsafe { free(ptr as *mut c_void) };
-            owned.into()
         } else {
             std::borrow::Cow::Owned(prompt.to_string())
         }
     }
 
     fn highlight_char(&self, line: &str, pos: usize, kind: CmdKind) -> bool {
+        if self.mask.is_some() {
+            return kind != CmdKind::MoveCursor;
+        }
         if let Some(cb) = self.char_highlighter_cb {
-            let c_line = CString::new(line).unwrap();
             cb(
                 self.k_callback_holder,
-                c_line.as_ptr(),
+                line.as_ptr(),
+                line.len() as c_int,
                 pos as c_int,
                 match kind {
                     CmdKind::MoveCursor => 0,
@@ -297,43 +706,33 @@ impl Validator for CustomHelper {
     fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
         if let Some(cb) = self.validator_cb {
             let line = ctx.input();
-            let c_line = CString::new(line).unwrap();
-            let mut msg_ptr: *mut c_char = std::ptr::null_mut();
+            let (pos, trigger) = match self.accept_state.take(line) {
+                Some(pos) => (pos, 0),
+                None => (line.len(), 1),
+            };
+            let mut msg_ptr: *mut u8 = std::ptr::null_mut();
+            let mut msg_len: c_int = 0;
             let code = cb(
                 self.k_callback_holder,
-                c_line.as_ptr(),
-                0 as c_int,
-                &mut msg_ptr as *mut *mut c_char,
+                line.as_ptr(),
+                line.len() as c_int,
+                pos as c_int,
+                trigger,
+                &mut msg_ptr as *mut *mut u8,
+                &mut msg_len as *mut c_int,
             );
+            // Taken whatever the result, so that it is never leaked.
+            let message = take_string(msg_ptr, msg_len);
             let res = match code {
-                0 => {
-                    if msg_ptr.is_null() {
-                        ValidationResult::Valid(None)
-                    } else {
-                        let owned = unsafe {
-                            CStr::from_ptr(msg_ptr as *const c_char)
-                                .to_string_lossy()
-                                .into_owned()
-                        };
-                        unsafe { free(msg_ptr as *mut c_void) };
-                        ValidationResult::Valid(Some(owned))
-                    }
-                }
-                1 => {
-                    if msg_ptr.is_null() {
-                        ValidationResult::Invalid(None)
-                    } else {
-                        let owned = unsafe {
-                            CStr::from_ptr(msg_ptr as *const c_char)
-                                .to_string_lossy()
-                                .into_owned()
-                        };
-                        unsafe { free(msg_ptr as *mut c_void) };
-                        ValidationResult::Invalid(Some(owned))
-                    }
-                }
+                0 => ValidationResult::Valid(message),
+                1 => ValidationResult::Invalid(message),
                 2 => ValidationResult::Incomplete,
-                _ => panic!("Unexpected error code from validator callback"),
+                _ => {
+                    return Err(ReadlineError::Io(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Unexpected result code from validator callback: {}", code),
+                    )))
+                }
             };
             Ok(res)
         } else {
@@ -351,315 +750,3269 @@ impl Validator for CustomHelper {
     }
 }
 
+/// Copies the `len` bytes at `bytes` into a buffer owned by the library, null if they are not
+/// a valid buffer. Strings returned by callbacks must be allocated with it, along with their
+/// length in bytes; the buffer of an empty string is not null.
 #[no_mangle]
-pub extern "C" fn free_read_line_result(ptr: *mut ReadLineResult) {
-    let ptr: ReadLineResult = unsafe { *Box::from_raw(ptr) };
+pub extern "C" fn readline4k_alloc_string(bytes: *const u8, len: c_int) -> *mut u8 {
+    catch_or(null_mut(), || match buf_to_bytes(bytes, len) {
+        Ok(bytes) => alloc_string(bytes),
+        Err(_) => null_mut(),
+    })
+}
+
+/// Frees a string of `len` bytes allocated by `readline4k_alloc_string`, does nothing if `ptr`
+/// is null.
+#[no_mangle]
+pub extern "C" fn readline4k_free_string(ptr: *mut u8, len: c_int) {
+    catch_or((), || {
+        take_string(ptr, len);
+    })
+}
 
-    if ptr.error >= 0 {
-        let error_message = unsafe { CString::from_raw(ptr.error_message) };
-        std::mem::drop(error_message);
+/// Allocates `len` completion candidates with null strings, or returns null if `len` is not
+/// positive. Arrays returned by the completer callback must be allocated with it.
+#[no_mangle]
+pub extern "C" fn readline4k_alloc_candidates(len: c_int) -> *mut CompletionCandidate {
+    if len <= 0 {
+        return null_mut();
     }
+    catch_or(null_mut(), || {
+        let candidates: Box<[CompletionCandidate]> = (0..len)
+            .map(|_| CompletionCandidate {
+                display: null_mut(),
+                display_len: 0,
+                replacement: null_mut(),
+                replacement_len: 0,
+                description: null_mut(),
+                description_len: 0,
+            })
+            .collect();
+        Box::into_raw(candidates) as *mut CompletionCandidate
+    })
+}
 
-    if ptr.result == null_mut() {
+/// Frees a result returned by the library, with its strings.
+// `ptr` must be null or a result returned by the library, as documented in the header.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn free_read_line_result(ptr: *mut ReadLineResult) {
+    if ptr.is_null() {
         return;
     }
+    catch_or((), || {
+        let ptr: ReadLineResult = unsafe { *Box::from_raw(ptr) };
+
+        if ptr.error >= 0 && !ptr.error_message.is_null() {
+            let error_message = unsafe { CString::from_raw(ptr.error_message) };
+            std::mem::drop(error_message);
+        }
 
-    let result = unsafe { CString::from_raw(ptr.result) };
-    std::mem::drop(result);
+        if ptr.result.is_null() {
+            return;
+        }
+
+        let result = std::ptr::slice_from_raw_parts_mut(
+            ptr.result as *mut u8,
+            ptr.result_len as usize + 1, // with the NUL terminator
+        );
+        let mut result = unsafe { Box::from_raw(result) };
+        // The result may be a password, see `editor_read_password`.
+        wipe(&mut result);
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn new_editor_with_config(
     cfg: *const EditorConfig,
     k_callback_holder: *mut c_void,
-) -> *mut c_void {
-    let cfg = unsafe { &*cfg };
-    let cfg = map_config(cfg);
+    out_editor: *mut EditorHandle,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        if cfg.is_null() || out_editor.is_null() {
+            return Err(FfiError::invalid_argument("Unexpected null pointer"));
+        }
+        let entry = create_editor(borrow_in(cfg)?, k_callback_holder, None)?;
+        write_out(out_editor, lock_registry(&EDITORS).insert(Arc::new(entry)));
+        Ok(())
+    })
+}
+
+/// Creates an editor attached to a pseudo-terminal of `columns` x `rows` instead of the real one.
+///
+/// rustyline only talks to the standard input and output, so they are redirected to the
+/// pseudo-terminal, for the whole process, while the editor uses it (e.g. during a read). Only one
+/// headless editor can do so at a time; the calls of the others fail with `ERROR_BUSY` meanwhile.
+#[no_mangle]
+pub extern "C" fn new_headless_editor(
+    cfg: *const EditorConfig,
+    k_callback_holder: *mut c_void,
+    columns: c_int,
+    rows: c_int,
+    out_editor: *mut EditorHandle,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        if cfg.is_null() || out_editor.is_null() {
+            return Err(FfiError::invalid_argument("Unexpected null pointer"));
+        }
+        let (Ok(columns @ 1..), Ok(rows @ 1..)) = (u16::try_from(columns), u16::try_from(rows))
+        else {
+            return Err(FfiError::invalid_argument("Invalid terminal size"));
+        };
+        // The editor must talk to the standard input and output, which get redirected.
+        let cfg = EditorConfig {
+            behavior: 0,
+            ..*borrow_in(cfg)?
+        };
+        let term = HeadlessTerm::open(columns, rows)?;
+        let entry = create_editor(&cfg, k_callback_holder, Some(term))?;
+        write_out(out_editor, lock_registry(&EDITORS).insert(Arc::new(entry)));
+        Ok(())
+    })
+}
+
+/// Whether reading with `config` shows the input as typed, instead of masked by the highlighter:
+/// rustyline echoes what it reads from a terminal, but only runs the highlighter with colors
+/// enabled, and a terminal it cannot drive echoes the input itself.
+#[cfg(unix)]
+fn echoes_unmasked(config: &Config) -> bool {
+    let term = TerminalIo::detect(config.behavior());
+    term.input
+        && (term.unsupported
+            || match config.color_mode() {
+                ColorMode::Enabled => !term.output,
+                ColorMode::Forced => false,
+                _ => true, // Disabled
+            })
+}
+
+#[cfg(not(unix))]
+fn echoes_unmasked(config: &Config) -> bool {
+    config.color_mode() == ColorMode::Disabled
+}
+
+/// Reads a line echoed as `mask` with a throwaway editor configured like `rl`, so that the
+/// password reaches neither the helper, key bindings and history of `rl`, nor its kill ring (to
+/// be yanked back in a later read).
+///
+/// rustyline does not expose the buffers of the throwaway editor (the line, kill ring and undo
+/// history), which are freed without being wiped.
+fn read_password(rl: &mut RlEditor, prompt: &str, mask: char) -> rustyline::Result<String> {
     let helper = CustomHelper {
-        k_callback_holder,
+        mask: Some(mask),
+        pending_read: rl
+            .helper()
+            .map(|h| h.pending_read.clone())
+            .unwrap_or_default(),
         ..Default::default()
     };
-    let mut rl: Editor<CustomHelper, FileHistory> = Editor::with_config(cfg).unwrap();
-    rl.set_helper(Some(helper));
-    let rl = Box::new(rl);
-    let rl = Box::leak(rl);
-    rl as *mut _ as *mut c_void
+    let config = rl.config_mut().clone();
+    let mut password: RlEditor = Editor::with_history(config, EditorHistory::disabled()?)?;
+    password.set_helper(Some(helper));
+    password.readline(prompt)
 }
 
-#[no_mangle]
-pub extern "C" fn editor_set_completer(rl: *mut c_void, cb: CompleterCallCb) {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    if let Some(h) = rl.helper_mut() {
-        h.completer_cb = Some(cb);
+fn create_editor(
+    cfg: &EditorConfig,
+    k_callback_holder: *mut c_void,
+    headless: Option<HeadlessTerm>,
+) -> Result<EditorEntry, FfiError> {
+    let config = map_config(cfg)?;
+    let history = EditorHistory::new(cfg, &config)?;
+    // The terminal is detected once, when the editor is created.
+    let create = || -> Result<EditorEntry, FfiError> {
+        let read = ReadState::new(config.behavior());
+        let helper = CustomHelper {
+            k_callback_holder,
+            handler_holder: Arc::new(AtomicPtr::new(k_callback_holder)),
+            pending_read: read.pending.clone(),
+            ..Default::default()
+        };
+        let accept_state = helper.accept_state.clone();
+        let tracker = AcceptTracker {
+            state: accept_state.clone(),
+            activity: read.activity.clone(),
+            binding: None,
+        };
+        let activity = ActivityTracker {
+            activity: read.activity.clone(),
+        };
+        let mut rl: RlEditor = Editor::with_history(config, history)?;
+        rl.set_helper(Some(helper));
+        rl.bind_sequence(
+            KeyEvent(KeyCode::Enter, Modifiers::NONE),
+            EventHandler::Conditional(Box::new(tracker)),
+        );
+        rl.bind_sequence(Event::Any, EventHandler::Conditional(Box::new(activity)));
+        Ok(EditorEntry {
+            editor: Mutex::new(rl),
+            read,
+            accept_state,
+            enter_bound: AtomicBool::new(false),
+            headless: None,
+        })
+    };
+    match headless {
+        None => create(),
+        Some(term) => {
+            let mut entry = term.redirect(create)??;
+            entry.headless = Some(term);
+            Ok(entry)
+        }
     }
 }
 
 #[no_mangle]
-pub extern "C" fn editor_set_highlighter(rl: *mut c_void, cb: HighlighterCb) {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    if let Some(h) = rl.helper_mut() {
-        h.highlighter_cb = Some(cb);
-    }
+pub extern "C" fn editor_set_completer(
+    rl: EditorHandle,
+    cb: CompleterCallCb,
+) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.completer_cb = Some(cb))
 }
 
 #[no_mangle]
-pub extern "C" fn editor_set_hint_highlighter(rl: *mut c_void, cb: HintHighlighterCb) {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    if let Some(h) = rl.helper_mut() {
-        h.hint_highlighter_cb = Some(cb);
-    }
+pub extern "C" fn editor_set_hinter(
+    rl: EditorHandle,
+    cb: HinterCb,
+    history_hints: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        if !(0..=2).contains(&history_hints) {
+            return Err(FfiError::invalid_enum("history_hints", history_hints));
+        }
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        if let Some(h) = rl.helper_mut() {
+            h.hinter_cb = Some(cb);
+            h.history_hints = history_hints;
+        }
+        Ok(())
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn editor_set_prompt_highlighter(rl: *mut c_void, cb: PromptHighlighterCb) {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    if let Some(h) = rl.helper_mut() {
-        h.prompt_highlighter_cb = Some(cb);
-    }
+pub extern "C" fn editor_set_highlighter(
+    rl: EditorHandle,
+    cb: HighlighterCb,
+) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.highlighter_cb = Some(cb))
 }
 
 #[no_mangle]
-pub extern "C" fn editor_set_candidate_highlighter(rl: *mut c_void, cb: CandidateHighlighterCb) {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    if let Some(h) = rl.helper_mut() {
-        h.candidate_highlighter_cb = Some(cb);
-    }
+pub extern "C" fn editor_set_hint_highlighter(
+    rl: EditorHandle,
+    cb: HintHighlighterCb,
+) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.hint_highlighter_cb = Some(cb))
 }
 
 #[no_mangle]
-pub extern "C" fn editor_set_char_highlighter(rl: *mut c_void, cb: CharHighlighterCb) {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    if let Some(h) = rl.helper_mut() {
-        h.char_highlighter_cb = Some(cb);
-    }
+pub extern "C" fn editor_set_prompt_highlighter(
+    rl: EditorHandle,
+    cb: PromptHighlighterCb,
+) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.prompt_highlighter_cb = Some(cb))
 }
 
 #[no_mangle]
-pub extern "C" fn editor_set_validator(rl: *mut c_void, cb: ValidatorCb) {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    if let Some(h) = rl.helper_mut() {
-        h.validator_cb = Some(cb);
-    }
+pub extern "C" fn editor_set_candidate_highlighter(
+    rl: EditorHandle,
+    cb: CandidateHighlighterCb,
+) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.candidate_highlighter_cb = Some(cb))
 }
 
 #[no_mangle]
-pub extern "C" fn editor_set_validator_while_typing(rl: *mut c_void, cb: ValidatorWhileTypingCb) {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    if let Some(h) = rl.helper_mut() {
-        h.validator_while_typing_cb = Some(cb);
-    }
+pub extern "C" fn editor_set_char_highlighter(
+    rl: EditorHandle,
+    cb: CharHighlighterCb,
+) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.char_highlighter_cb = Some(cb))
 }
 
 #[no_mangle]
-pub extern "C" fn editor_read_line(rl: *mut c_void, prefix: *const c_char) -> *mut ReadLineResult {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    let prefix = c_chars_to_str(prefix);
-    let readline: Result<String, ReadlineError> = rl.readline(prefix);
-    handle_readline_result(readline)
+pub extern "C" fn editor_set_validator(rl: EditorHandle, cb: ValidatorCb) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.validator_cb = Some(cb))
 }
 
 #[no_mangle]
-pub extern "C" fn editor_load_history(rl: *mut c_void, path: *const c_char) -> *mut ReadLineResult {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    let path = c_chars_to_str(path);
-    let result = rl.load_history(path);
-    handle_simple_result(result)
+pub extern "C" fn editor_set_validator_while_typing(
+    rl: EditorHandle,
+    cb: ValidatorWhileTypingCb,
+) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.validator_while_typing_cb = Some(cb))
 }
 
 #[no_mangle]
-pub extern "C" fn editor_add_history_entry(rl: *mut c_void, entry: *const c_char) {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    let entry = c_chars_to_str(entry);
-    rl.add_history_entry(entry).unwrap();
+pub extern "C" fn editor_unset_completer(rl: EditorHandle) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.completer_cb = None)
 }
 
 #[no_mangle]
-pub extern "C" fn editor_save_history(rl: *mut c_void, path: *const c_char) -> *mut ReadLineResult {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    let path = c_chars_to_str(path);
-    let result = rl.save_history(path);
-    handle_simple_result(result)
+pub extern "C" fn editor_unset_hinter(rl: EditorHandle) -> *mut ReadLineResult {
+    update_helper(rl, |h| {
+        h.hinter_cb = None;
+        h.history_hints = 0;
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn editor_clear_history(rl: *mut c_void) -> *mut ReadLineResult {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    let result = rl.clear_history();
-    handle_simple_result(result)
+pub extern "C" fn editor_unset_highlighter(rl: EditorHandle) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.highlighter_cb = None)
 }
 
 #[no_mangle]
-pub extern "C" fn editor_clear_screen(rl: *mut c_void) -> *mut ReadLineResult {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    let result = rl.clear_screen();
-    handle_simple_result(result)
+pub extern "C" fn editor_unset_hint_highlighter(rl: EditorHandle) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.hint_highlighter_cb = None)
 }
 
 #[no_mangle]
-pub extern "C" fn editor_set_cursor_visibility(rl: *mut c_void, visible: bool) {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    rl.set_cursor_visibility(visible).unwrap();
+pub extern "C" fn editor_unset_prompt_highlighter(rl: EditorHandle) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.prompt_highlighter_cb = None)
 }
 
 #[no_mangle]
-pub extern "C" fn editor_set_auto_add_history(rl: *mut c_void, value: bool) {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    rl.set_auto_add_history(value);
+pub extern "C" fn editor_unset_candidate_highlighter(rl: EditorHandle) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.candidate_highlighter_cb = None)
 }
 
 #[no_mangle]
-pub extern "C" fn editor_set_color_mode(rl: *mut c_void, value: c_int) {
-    let rl = unsafe { &mut *(rl as *mut Editor<CustomHelper, FileHistory>) };
-    let color_mode = match value {
-        0 => ColorMode::Enabled,
-        2 => ColorMode::Forced,
-        3 => ColorMode::Disabled,
-        _ => panic!("Invalid color mode value"),
-    };
-    rl.set_color_mode(color_mode);
+pub extern "C" fn editor_unset_char_highlighter(rl: EditorHandle) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.char_highlighter_cb = None)
 }
 
 #[no_mangle]
-pub extern "C" fn free_editor(ptr: *mut c_void) {
-    let _editor: Box<Editor<CustomHelper, FileHistory>> = unsafe { Box::from_raw(ptr as *mut _) };
-    // Box will be dropped automatically
+pub extern "C" fn editor_unset_validator(rl: EditorHandle) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.validator_cb = None)
 }
 
-fn handle_readline_result(readline: Result<String, ReadlineError>) -> *mut ReadLineResult {
-    match readline {
-        Ok(line) => {
-            let result = ReadLineResult {
-                result: CString::new(line).unwrap().into_raw(),
-                ..Default::default()
-            };
-            result.leak()
+#[no_mangle]
+pub extern "C" fn editor_unset_validator_while_typing(rl: EditorHandle) -> *mut ReadLineResult {
+    update_helper(rl, |h| h.validator_while_typing_cb = None)
+}
+
+#[no_mangle]
+pub extern "C" fn editor_reset_helper(
+    rl: EditorHandle,
+    k_callback_holder: *mut c_void,
+) -> *mut ReadLineResult {
+    update_helper(rl, |h| {
+        // The bound event handlers switch to the new holder as well.
+        h.handler_holder.store(k_callback_holder, Ordering::Release);
+        *h = CustomHelper {
+            k_callback_holder,
+            handler_holder: h.handler_holder.clone(),
+            // Shared with the handler bound to Enter.
+            accept_state: h.accept_state.clone(),
+            // Shared with the editor.
+            pending_read: h.pending_read.clone(),
+            ..Default::default()
         }
-        Err(ReadlineError::Eof) => {
-            let error_message = CString::new("Reached end of file").unwrap().into_raw();
-            let result = ReadLineResult {
-                error: ERROR_EOF,
-                error_message,
-                ..Default::default()
-            };
-            result.leak()
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_bind_sequence(
+    rl: EditorHandle,
+    keys: *const EditorKeyEvent,
+    keys_len: c_int,
+    cmd: *const EditorCommand,
+    out_replaced: *mut bool,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let event = map_key_sequence(keys, keys_len)?;
+        if cmd.is_null() {
+            return Err(FfiError::invalid_argument(
+                "Unexpected null command pointer",
+            ));
         }
-        Err(ReadlineError::Interrupted) => {
-            let error_message = CString::new("Received interrupt signal")
-                .unwrap()
-                .into_raw();
-            let result = ReadLineResult {
-                error: ERROR_INTERRUPTED,
-                error_message,
-                ..Default::default()
-            };
-            result.leak()
+        let binding = CommandBinding {
+            cmd: map_command(borrow_in(cmd)?)?,
+            activity: editor.read.activity.clone(),
+        };
+        let replaced = editor.bind_sequence(&mut rl, event, Some(Box::new(binding)));
+        write_out(out_replaced, replaced);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_bind_sequence_handler(
+    rl: EditorHandle,
+    keys: *const EditorKeyEvent,
+    keys_len: c_int,
+    cb: EventHandlerCb,
+    handler_id: c_int,
+    out_replaced: *mut bool,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let event = map_key_sequence(keys, keys_len)?;
+        let k_callback_holder = match rl.helper() {
+            Some(h) => h.handler_holder.clone(),
+            None => Default::default(),
+        };
+        let handler = CallbackEventHandler {
+            cb,
+            handler_id,
+            k_callback_holder,
+            activity: editor.read.activity.clone(),
+        };
+        let replaced = editor.bind_sequence(&mut rl, event, Some(Box::new(handler)));
+        write_out(out_replaced, replaced);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_unbind_sequence(
+    rl: EditorHandle,
+    keys: *const EditorKeyEvent,
+    keys_len: c_int,
+    out_removed: *mut bool,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let event = map_key_sequence(keys, keys_len)?;
+        let removed = editor.bind_sequence(&mut rl, event, None);
+        write_out(out_removed, removed);
+        Ok(())
+    })
+}
+
+/// Writes the `keys_len` keys at `keys` to `out_keys` as they are bound, e.g. `Ctrl-a` as `Ctrl-A`.
+#[no_mangle]
+pub extern "C" fn readline4k_normalize_key_sequence(
+    keys: *const EditorKeyEvent,
+    keys_len: c_int,
+    out_keys: *mut EditorKeyEvent,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let Event::KeySeq(keys) = map_key_sequence(keys, keys_len)? else {
+            return Err(FfiError::invalid_argument("Invalid key sequence"));
+        };
+        if out_keys.is_null() {
+            return Err(FfiError::invalid_argument("Unexpected null pointer"));
         }
-        Err(err) => {
-            let error_message = CString::new(format!("Unknown error: {:?}", err))
-                .unwrap()
-                .into_raw();
-            let result = ReadLineResult {
-                error: ERROR_UNKNOWN,
-                error_message,
-                ..Default::default()
-            };
-            result.leak()
+        for (i, key) in keys.into_iter().enumerate() {
+            write_out(
+                out_keys.wrapping_add(i),
+                unmap_key_event(KeyEvent::normalize(key))?,
+            );
         }
-    }
+        Ok(())
+    })
 }
 
-fn handle_simple_result(res: Result<(), ReadlineError>) -> *mut ReadLineResult {
-    match res {
-        Ok(_) => {
-            let result = ReadLineResult {
-                ..Default::default()
-            };
-            result.leak()
+#[no_mangle]
+pub extern "C" fn editor_read_line(
+    rl: EditorHandle,
+    prefix: *const u8,
+    prefix_len: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let prefix = buf_to_str(prefix, prefix_len)?;
+        editor.read_line(|rl| rl.readline(prefix))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_read_line_with_timeout(
+    rl: EditorHandle,
+    prefix: *const u8,
+    prefix_len: c_int,
+    timeout_ms: c_int,
+    idle: bool, // true = restart the timeout on every key, false = the whole line must be entered in time
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        if timeout_ms < 0 {
+            return Err(FfiError::invalid_argument("Negative timeout"));
         }
-        Err(err) => {
-            let error_message = CString::new(format!("Unknown error: {:?}", err))
-                .unwrap()
-                .into_raw();
-            let result = ReadLineResult {
-                error: ERROR_UNKNOWN,
-                error_message,
-                ..Default::default()
-            };
-            result.leak()
+        let editor = editor(rl)?;
+        let prefix = buf_to_str(prefix, prefix_len)?;
+        let timeout = Duration::from_millis(timeout_ms as u64);
+        editor.read_line_with_timeout(timeout, idle, |rl| rl.readline(prefix))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_read_line_with_initial(
+    rl: EditorHandle,
+    prefix: *const u8,
+    prefix_len: c_int,
+    left: *const u8,
+    left_len: c_int,
+    right: *const u8,
+    right_len: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let prefix = buf_to_str(prefix, prefix_len)?;
+        let left = buf_to_str(left, left_len)?;
+        let right = buf_to_str(right, right_len)?;
+        editor.read_line(|rl| rl.readline_with_initial(prefix, (left, right)))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_read_password(
+    rl: EditorHandle,
+    prefix: *const u8,
+    prefix_len: c_int,
+    mask: u32,
+    timeout_ms: c_int, // -1 = no timeout, otherwise restarted on every key like an idle timeout
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let prefix = buf_to_str(prefix, prefix_len)?;
+        let mask = map_char(mask)?;
+        if mask.is_control() {
+            return Err(FfiError::invalid_argument("Invalid mask character"));
         }
-    }
+        let timeout = match timeout_ms {
+            -1 => None,
+            0.. => Some(Duration::from_millis(timeout_ms as u64)),
+            _ => return Err(FfiError::invalid_argument("Negative timeout")),
+        };
+        let config = editor.lock()?.config_mut().clone();
+        if editor.on_terminal(|| echoes_unmasked(&config))? {
+            return Err(FfiError::new(
+                ERROR_UNSUPPORTED,
+                "The password would be echoed unmasked, without colors or on an unsupported terminal"
+                    .to_string(),
+            ));
+        }
+        let read = |rl: &mut RlEditor| read_password(rl, prefix, mask);
+        let mut password = match timeout {
+            None => editor.read_line(read)?,
+            Some(timeout) => editor.read_line_with_timeout(timeout, true, read)?,
+        };
+        // Copied with room for the NUL terminator, so that the result is not reallocated.
+        let mut result = String::with_capacity(password.len() + 1);
+        result.push_str(&password);
+        wipe(unsafe { password.as_bytes_mut() });
+        Ok(result)
+    })
 }
 
-extern "C" {
-    fn free(ptr: *mut c_void);
+#[no_mangle]
+pub extern "C" fn editor_cancel_read_line(
+    rl: EditorHandle,
+    out_cancelled: *mut bool,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let cancelled = editor(rl)?.read.cancel()?;
+        write_out(out_cancelled, cancelled);
+        Ok(())
+    })
 }
 
-fn map_config(cfg: &EditorConfig) -> Config {
-    let history_dupes = match cfg.history_duplicates {
-        0 => HistoryDuplicates::AlwaysAdd,
-        1 => HistoryDuplicates::IgnoreConsecutive,
-        _ => panic!("Invalid history_duplicates value"),
-    };
-    let completion_type = match cfg.completion_type {
-        0 => CompletionType::Circular,
-        1 => CompletionType::List,
-        _ => panic!("Invalid completion_type value"),
-    };
-    let edit_mode = match cfg.edit_mode {
-        0 => EditMode::Emacs,
-        1 => EditMode::Vi,
-        _ => panic!("Invalid edit_mode value"),
-    };
-    let bell_style = match cfg.bell_style {
-        0 => BellStyle::Audible,
-        1 => BellStyle::None,
-        2 => BellStyle::Visible,
-        _ => panic!("Invalid bell_style value"),
-    };
-    let color_mode = match cfg.color_mode {
-        0 => ColorMode::Enabled,
-        1 => ColorMode::Forced,
-        2 => ColorMode::Disabled,
-        _ => panic!("Invalid color_mode value"),
-    };
-    let behavior = match cfg.behavior {
-        0 => Behavior::Stdio,
-        1 => Behavior::PreferTerm,
-        _ => panic!("Invalid behavior value"),
-    };
-    let keyseq_timeout: Option<u16> = if cfg.key_seq_timeout >= 0 {
-        Some(cfg.key_seq_timeout as u16)
-    } else {
-        None
-    };
+#[no_mangle]
+pub extern "C" fn editor_feed_input(
+    rl: EditorHandle,
+    input: *const u8,
+    input_len: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let input = buf_to_bytes(input, input_len)?;
+        let editor = editor(rl)?;
+        if input.is_empty() {
+            return editor.headless().map(|_| ());
+        }
+        editor.headless()?.feed(input)
+    })
+}
 
-    let mut builder = Config::builder();
-    builder = builder
-        .max_history_size(cfg.max_history_size.max(0) as usize)
-        .unwrap();
-    // Fallback to boolean API for duplicates handling
-    builder = builder
-        .history_ignore_dups(!matches!(history_dupes, HistoryDuplicates::AlwaysAdd))
-        .unwrap();
-    builder = builder.history_ignore_space(cfg.history_ignore_space);
-    builder = builder.completion_type(completion_type);
-    builder = builder.completion_show_all_if_ambiguous(cfg.completion_show_all_if_ambiguous);
-    builder = builder.completion_prompt_limit(cfg.completion_prompt_limit.max(0) as usize);
-    builder = builder.keyseq_timeout(keyseq_timeout);
-    builder = builder.edit_mode(edit_mode);
-    builder = builder.auto_add_history(cfg.auto_add_history);
-    builder = builder.bell_style(bell_style);
-    builder = builder.color_mode(color_mode);
-    builder = builder.behavior(behavior);
-    builder = builder.tab_stop(cfg.tab_stop);
-    builder = builder.indent_size(cfg.indent_size);
-    builder = builder.check_cursor_position(cfg.check_cursor_position);
-    builder = builder.bracketed_paste(cfg.enable_bracketed_paste);
-    builder.enable_synchronized_output(cfg.enable_synchronized_output);
-    builder = builder.enable_signals(cfg.enable_signals);
-    builder.build()
+#[no_mangle]
+pub extern "C" fn editor_feed_keys(
+    rl: EditorHandle,
+    keys: *const EditorKeyEvent,
+    keys_len: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let Event::KeySeq(keys) = map_key_sequence(keys, keys_len)? else {
+            return Err(FfiError::invalid_argument("Invalid key sequence"));
+        };
+        let mut input = Vec::new();
+        for key in keys {
+            encode_key(key, &mut input)?;
+        }
+        editor.headless()?.feed(&input)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_take_output(rl: EditorHandle) -> *mut ReadLineResult {
+    ffi_result(|| editor(rl)?.headless()?.take_output())
+}
+
+#[no_mangle]
+pub extern "C" fn editor_read_line_async(
+    rl: EditorHandle,
+    prefix: *const u8,
+    prefix_len: c_int,
+    out_request: *mut ReadRequestHandle,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        if out_request.is_null() {
+            return Err(FfiError::invalid_argument("Unexpected null pointer"));
+        }
+        let editor = editor(rl)?;
+        let prefix = buf_to_str(prefix, prefix_len)?.to_string();
+        let request = Arc::new(ReadRequest {
+            editor,
+            cancelled: AtomicBool::new(false),
+            result: Mutex::new(None),
+            done: Condvar::new(),
+        });
+        let worker = request.clone();
+        std::thread::Builder::new()
+            .name("readline4k-read".to_string())
+            .spawn(move || worker.run(prefix))
+            .map_err(ReadlineError::Io)?;
+        write_out(out_request, lock_registry(&READ_REQUESTS).insert(request));
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn read_request_poll(
+    request: ReadRequestHandle,
+    out_done: *mut bool,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let request = read_request(request)?;
+        let result = request
+            .result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        write_out(out_done, result.is_some());
+        result.clone().transpose()
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn read_request_await(
+    request: ReadRequestHandle,
+    timeout_ms: c_int, // -1 means wait until the read completes
+    out_done: *mut bool,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let request = read_request(request)?;
+        let result = request
+            .result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let result = if timeout_ms < 0 {
+            request.done.wait_while(result, |result| result.is_none())
+        } else {
+            let timeout = Duration::from_millis(timeout_ms as u64);
+            request
+                .done
+                .wait_timeout_while(result, timeout, |result| result.is_none())
+                .map(|(result, _)| result)
+                .map_err(|poisoned| PoisonError::new(poisoned.into_inner().0))
+        };
+        let result = result.unwrap_or_else(PoisonError::into_inner);
+        write_out(out_done, result.is_some());
+        result.clone().transpose()
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn read_request_cancel(
+    request: ReadRequestHandle,
+    out_cancelled: *mut bool,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let cancelled = read_request(request)?.cancel()?;
+        write_out(out_cancelled, cancelled);
+        Ok(())
+    })
 }
 
-fn c_chars_to_str<'a>(c_chars: *const c_char) -> &'a str {
-    unsafe { CStr::from_ptr(c_chars).to_str().unwrap() }
+#[no_mangle]
+pub extern "C" fn free_read_request(request: ReadRequestHandle) {
+    catch_or((), || {
+        let _request = lock_registry(&READ_REQUESTS).remove(request);
+        // A read still in progress keeps running, its result is discarded
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_load_history(
+    rl: EditorHandle,
+    path: *const u8,
+    path_len: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let path = buf_to_str(path, path_len)?;
+        rl.history().check_persistent()?;
+        rl.load_history(path)?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_add_history_entry(
+    rl: EditorHandle,
+    entry: *const u8,
+    entry_len: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let entry = buf_to_str(entry, entry_len)?;
+        rl.add_history_entry(entry)?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_save_history(
+    rl: EditorHandle,
+    path: *const u8,
+    path_len: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let path = buf_to_str(path, path_len)?;
+        rl.history().check_persistent()?;
+        rl.save_history(path)?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_append_history(
+    rl: EditorHandle,
+    path: *const u8,
+    path_len: c_int,
+    merge: bool, // reload the file afterward, to pick up the entries appended by other editors
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let path = buf_to_str(path, path_len)?;
+        rl.history().check_persistent()?;
+        // Only the entries added since the last save are written, with the file locked.
+        rl.append_history(path)?;
+        if merge {
+            let config = rl.config_mut().clone();
+            rl.history_mut().reload(Path::new(path), &config)?;
+        }
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_clear_history(rl: EditorHandle) -> *mut ReadLineResult {
+    ffi_result(|| {
+        editor(rl)?.lock()?.clear_history()?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_history_len(rl: EditorHandle, out_len: *mut c_int) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let len = editor.lock()?.history().len();
+        write_out(out_len, len as c_int);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_history_get(rl: EditorHandle, index: c_int) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let rl = editor.lock()?;
+        if index < 0 {
+            return Ok(None);
+        }
+        let result = rl.history().get(index as usize, SearchDirection::Forward)?;
+        Ok(take_search_result(result, null_mut(), null_mut()))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_history_search(
+    rl: EditorHandle,
+    term: *const u8,
+    term_len: c_int,
+    start: c_int,
+    direction: c_int,
+    out_idx: *mut c_int,
+    out_pos: *mut c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let rl = editor.lock()?;
+        let term = buf_to_str(term, term_len)?;
+        let direction = map_search_direction(direction)?;
+        if start < 0 {
+            return Ok(None);
+        }
+        let result = rl.history().search(term, start as usize, direction)?;
+        Ok(take_search_result(result, out_idx, out_pos))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_history_starts_with(
+    rl: EditorHandle,
+    term: *const u8,
+    term_len: c_int,
+    start: c_int,
+    direction: c_int,
+    out_idx: *mut c_int,
+    out_pos: *mut c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let rl = editor.lock()?;
+        let term = buf_to_str(term, term_len)?;
+        let direction = map_search_direction(direction)?;
+        if start < 0 {
+            return Ok(None);
+        }
+        let result = rl.history().starts_with(term, start as usize, direction)?;
+        Ok(take_search_result(result, out_idx, out_pos))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_history_get_details(
+    rl: EditorHandle,
+    index: c_int,
+    out_session_id: *mut i64,
+    out_timestamp: *mut i64, // millis since the epoch
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let rl = editor.lock()?;
+        if index < 0 {
+            return Ok(None);
+        }
+        let Some((entry, session_id, timestamp)) = rl.history().details(index as usize)? else {
+            return Ok(None);
+        };
+        write_out(out_session_id, session_id);
+        write_out(out_timestamp, timestamp);
+        Ok(Some(entry))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_history_session_id(
+    rl: EditorHandle,
+    out_session_id: *mut i64, // 0 until this editor adds an entry
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let session_id = editor.lock()?.history().session_id()?;
+        write_out(out_session_id, session_id);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_clear_screen(rl: EditorHandle) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        editor.on_terminal(|| rl.clear_screen())??;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_cursor_visibility(
+    rl: EditorHandle,
+    visible: bool,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        editor.on_terminal(|| rl.set_cursor_visibility(visible))??;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_auto_add_history(
+    rl: EditorHandle,
+    value: bool,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        editor(rl)?.lock()?.set_auto_add_history(value);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_color_mode(rl: EditorHandle, value: c_int) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let color_mode = map_color_mode(value)?;
+        rl.set_color_mode(color_mode);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_get_config(
+    rl: EditorHandle,
+    out_config: *mut EditorConfig, // history_path is set to null, the result holds the path
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        if out_config.is_null() {
+            return Err(FfiError::invalid_argument("Unexpected null pointer"));
+        }
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let (history_backend, history_path) = rl.history().backend();
+        let cfg = unmap_config(rl.config_mut(), history_backend);
+        write_out(out_config, cfg);
+        Ok(history_path)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_edit_mode(rl: EditorHandle, value: c_int) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let edit_mode = map_edit_mode(value)?;
+        // Also resets the key sequence timeout to the default of the mode.
+        rl.set_edit_mode(edit_mode);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_completion_type(
+    rl: EditorHandle,
+    value: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let completion_type = map_completion_type(value)?;
+        rl.set_completion_type(completion_type);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_completion_show_all_if_ambiguous(
+    rl: EditorHandle,
+    value: bool,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        editor(rl)?
+            .lock()?
+            .set_completion_show_all_if_ambiguous(value);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_completion_prompt_limit(
+    rl: EditorHandle,
+    value: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        rl.set_completion_prompt_limit(map_size("completion_prompt_limit", value)?);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_max_history_size(
+    rl: EditorHandle,
+    value: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        // Drops the oldest entries that no longer fit.
+        rl.set_max_history_size(map_size("max_history_size", value)?)?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_history_duplicates(
+    rl: EditorHandle,
+    value: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let history_dupes = map_history_duplicates(value)?;
+        rl.set_history_ignore_dups(!matches!(history_dupes, HistoryDuplicates::AlwaysAdd))?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_history_ignore_space(
+    rl: EditorHandle,
+    value: bool,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        editor(rl)?.lock()?.set_history_ignore_space(value);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_key_seq_timeout(
+    rl: EditorHandle,
+    value: c_int, // millis, -1 means None
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let keyseq_timeout = map_keyseq_timeout(value)?;
+        editor(rl)?.lock()?.set_keyseq_timeout(keyseq_timeout);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_bell_style(rl: EditorHandle, value: c_int) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        let mut rl = editor.lock()?;
+        let bell_style = map_bell_style(value)?;
+        rl.set_bell_style(bell_style);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_tab_stop(rl: EditorHandle, value: u8) -> *mut ReadLineResult {
+    ffi_result(|| {
+        if value == 0 {
+            return Err(FfiError::invalid_argument("Tab stop must be positive"));
+        }
+        editor(rl)?.lock()?.set_tab_stop(value);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_indent_size(rl: EditorHandle, value: u8) -> *mut ReadLineResult {
+    ffi_result(|| {
+        editor(rl)?.lock()?.set_indent_size(value);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_set_check_cursor_position(
+    rl: EditorHandle,
+    value: bool,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        editor(rl)?.lock()?.set_check_cursor_position(value);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn editor_create_external_printer(
+    rl: EditorHandle,
+    out_printer: *mut PrinterHandle,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        if out_printer.is_null() {
+            return Err(FfiError::invalid_argument("Unexpected null pointer"));
+        }
+        let printer = editor(rl)?.lock()?.create_external_printer()?;
+        let printer: SharedPrinter = Mutex::new(Box::new(printer));
+        write_out(
+            out_printer,
+            lock_registry(&PRINTERS).insert(Arc::new(printer)),
+        );
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn external_printer_print(
+    printer: PrinterHandle,
+    msg: *const u8,
+    msg_len: c_int,
+) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let printer = lock_registry(&PRINTERS)
+            .get(printer)
+            .ok_or_else(|| FfiError::disposed("External printer"))?;
+        let msg = buf_to_str(msg, msg_len)?.to_string();
+        printer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .print(msg)?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn free_external_printer(printer: PrinterHandle) {
+    catch_or((), || {
+        let _printer = lock_registry(&PRINTERS).remove(printer);
+        // Dropped once the last in-flight call releases it
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn free_editor(rl: EditorHandle) {
+    catch_or((), || {
+        let _editor = lock_registry(&EDITORS).remove(rl);
+        // Dropped once the last in-flight call releases it
+    })
+}
+
+/// Maps opaque handles to live objects, so that stale handles are detected instead of
+/// dereferenced.
+///
+/// A handle packs the slot index (low 32 bits) with the slot generation (high 32 bits).
+/// The generation is bumped every time a slot is freed, so a handle is never valid again
+/// once removed, even if its slot is reused.
+struct Registry<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<Arc<T>>,
+}
+
+impl<T> Registry<T> {
+    const fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, value: Arc<T>) -> u64 {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                // Generations start at 1, so that 0 is never a valid handle.
+                self.slots.push(Slot {
+                    generation: 1,
+                    value: None,
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.value = Some(value);
+        ((slot.generation as u64) << 32) | index as u64
+    }
+
+    fn get(&self, handle: u64) -> Option<Arc<T>> {
+        self.slot(handle)?.value.clone()
+    }
+
+    fn remove(&mut self, handle: u64) -> Option<Arc<T>> {
+        let index = handle as u32;
+        self.slot(handle)?.value.as_ref()?;
+        let slot = &mut self.slots[index as usize];
+        slot.generation = slot.generation.checked_add(1).unwrap_or(1);
+        self.free.push(index);
+        slot.value.take()
+    }
+
+    fn slot(&self, handle: u64) -> Option<&Slot<T>> {
+        let (index, generation) = (handle as u32, (handle >> 32) as u32);
+        self.slots
+            .get(index as usize)
+            .filter(|slot| slot.generation == generation)
+    }
+}
+
+fn lock_registry<T>(registry: &Mutex<Registry<T>>) -> MutexGuard<'_, Registry<T>> {
+    registry.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A live editor, shared by the registry and the calls currently using it.
+struct EditorEntry {
+    editor: Mutex<RlEditor>,
+    read: ReadState,
+    accept_state: Arc<AcceptState>, // shared with the helper and the [AcceptTracker]
+    enter_bound: AtomicBool,        // whether the [AcceptTracker] wraps a binding of the user
+    headless: Option<HeadlessTerm>,
+}
+
+impl EditorEntry {
+    /// Binds `binding` to `event`, or unbinds `event` if `binding` is None. Returns whether a
+    /// binding of the user was replaced (or removed).
+    ///
+    /// Enter stays bound to the [AcceptTracker], which runs the binding of the user instead.
+    fn bind_sequence(
+        &self,
+        rl: &mut RlEditor,
+        event: Event,
+        binding: Option<Box<dyn ConditionalEventHandler>>,
+    ) -> bool {
+        let is_enter = match &event {
+            Event::KeySeq(keys) => {
+                keys.len() == 1
+                    && KeyEvent::normalize(keys[0]) == KeyEvent(KeyCode::Enter, Modifiers::NONE)
+            }
+            _ => false,
+        };
+        if !is_enter {
+            return match binding {
+                Some(binding) => rl
+                    .bind_sequence(event, EventHandler::Conditional(binding))
+                    .is_some(),
+                None => rl.unbind_sequence(event).is_some(),
+            };
+        }
+        let bound = binding.is_some();
+        let tracker = AcceptTracker {
+            state: self.accept_state.clone(),
+            activity: self.read.activity.clone(),
+            binding,
+        };
+        rl.bind_sequence(event, EventHandler::Conditional(Box::new(tracker)));
+        self.enter_bound.swap(bound, Ordering::SeqCst)
+    }
+
+    /// Locks the editor, failing instead of blocking if it is in use (e.g. by a pending
+    /// `editor_read_line` whose callbacks call back into the editor).
+    fn lock(&self) -> Result<MutexGuard<'_, RlEditor>, FfiError> {
+        match self.editor.try_lock() {
+            Ok(rl) => Ok(rl),
+            Err(TryLockError::Poisoned(poisoned)) => Ok(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => {
+                Err(FfiError::new(ERROR_BUSY, "Editor is busy".to_string()))
+            }
+        }
+    }
+
+    /// Runs `f`, which uses the terminal, on the pseudo-terminal of a headless editor.
+    fn on_terminal<T>(&self, f: impl FnOnce() -> T) -> Result<T, FfiError> {
+        match &self.headless {
+            None => Ok(f()),
+            Some(term) => term.redirect(f),
+        }
+    }
+
+    fn headless(&self) -> Result<&HeadlessTerm, FfiError> {
+        self.headless
+            .as_ref()
+            .ok_or_else(|| FfiError::invalid_argument("Not a headless editor"))
+    }
+
+    /// Runs `read` as the pending read of this editor, so that it can be cancelled.
+    fn read_line<F>(&self, read: F) -> Result<String, FfiError>
+    where
+        F: FnOnce(&mut RlEditor) -> rustyline::Result<String>,
+    {
+        let mut rl = self.lock()?;
+        let pending = self.read.begin();
+        let result = self.on_terminal(|| read(&mut rl))?;
+        drop(pending);
+        match result {
+            Err(ReadlineError::Interrupted) => match self.read.interrupted.load(Ordering::SeqCst) {
+                OK => Err(ReadlineError::Interrupted.into()),
+                reason => Err(FfiError::interrupted(reason)),
+            },
+            result => Ok(result?),
+        }
+    }
+
+    /// Runs `read` like [Self::read_line], interrupting it once `timeout` elapses since it
+    /// started or, if `idle`, since the last key typed.
+    fn read_line_with_timeout<F>(
+        &self,
+        timeout: Duration,
+        idle: bool,
+        read: F,
+    ) -> Result<String, FfiError>
+    where
+        F: FnOnce(&mut RlEditor) -> rustyline::Result<String>,
+    {
+        if !self.read.interruptible {
+            return Err(FfiError::new(
+                ERROR_UNSUPPORTED,
+                "Only reads from an interactive terminal can time out".to_string(),
+            ));
+        }
+        let finished = Mutex::new(false);
+        let wake = Condvar::new();
+        let started = Instant::now();
+        std::thread::scope(|scope| {
+            let watchdog = std::thread::Builder::new()
+                .name("readline4k-timeout".to_string())
+                .spawn_scoped(scope, || {
+                    let mut finished = finished.lock().unwrap_or_else(PoisonError::into_inner);
+                    while !*finished {
+                        let since = match self.read.activity.last() {
+                            Some(last) if idle => last.max(started),
+                            _ => started,
+                        };
+                        let now = Instant::now();
+                        let wait = match (since + timeout).checked_duration_since(now) {
+                            Some(wait) if !wait.is_zero() => wait,
+                            // Retried shortly if the read is not pending yet.
+                            _ if matches!(self.read.interrupt(ERROR_TIMEOUT), Ok(true)) => return,
+                            _ => Duration::from_millis(10),
+                        };
+                        finished = wake
+                            .wait_timeout(finished, wait)
+                            .map(|(finished, _)| finished)
+                            .unwrap_or_else(|poisoned| poisoned.into_inner().0);
+                    }
+                })
+                .map_err(ReadlineError::Io)?;
+            let result = self.read_line(read);
+            *finished.lock().unwrap_or_else(PoisonError::into_inner) = true;
+            wake.notify_all();
+            let _ = watchdog.join();
+            result
+        })
+    }
+}
+
+/// Tracks the pending read of an editor, so that it can be cancelled (or time out) from another thread.
+///
+/// A read is interrupted by signalling the reading thread with SIGINT, which rustyline turns
+/// into `Interrupted` after restoring the terminal mode. rustyline only handles SIGINT while it
+/// reads from an interactive terminal: it installs its handler once the read has started, and
+/// removes it before `readline` returns. So a read only becomes interruptible once its first
+/// hint is asked for, right after the handler is installed, an interrupt requested earlier being
+/// delivered then. rustyline only notices the signal when it interrupts a blocking read of the
+/// terminal though, so the thread is signalled again and again until the read returns. And SIGINT
+/// is ignored for as long as the read is pending, so that a signal that comes once the handler is
+/// removed is dropped instead of killing the process.
+///
+/// Reads from anything but an interactive terminal cannot be interrupted.
+struct ReadState {
+    interruptible: bool,
+    pending: Arc<SharedPendingRead>,
+    interrupted: AtomicI32, // the error reported for the interrupted read, OK if it was not interrupted
+    activity: Arc<KeyActivity>,
+}
+
+#[derive(Clone, Copy)]
+enum PendingRead {
+    /// rustyline does not handle SIGINT yet, `interrupt` tells whether to signal the thread once it does.
+    #[cfg(unix)]
+    Starting {
+        thread: libc::pthread_t,
+        interrupt: bool,
+    },
+    #[cfg(unix)]
+    Interruptible(libc::pthread_t),
+    /// The thread is signalled until the read returns, see [SharedPendingRead::signal].
+    #[cfg(unix)]
+    Interrupted(libc::pthread_t),
+    Blocking,
+}
+
+/// The pending read of an editor, shared with its helper.
+#[derive(Default)]
+struct SharedPendingRead(Mutex<Option<PendingRead>>);
+
+impl SharedPendingRead {
+    fn lock(&self) -> MutexGuard<'_, Option<PendingRead>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Makes the pending read interruptible, now that rustyline handles SIGINT.
+    fn arm(self: &Arc<Self>) {
+        #[cfg(unix)]
+        {
+            let mut pending = self.lock();
+            if let Some(PendingRead::Starting { thread, interrupt }) = *pending {
+                if interrupt {
+                    *pending = Some(PendingRead::Interrupted(thread));
+                    self.signal(thread);
+                } else {
+                    *pending = Some(PendingRead::Interruptible(thread));
+                }
+            }
+        }
+    }
+
+    /// Signals `thread` with SIGINT every few milliseconds for as long as its read is
+    /// `Interrupted`, called with the lock held once it is.
+    ///
+    /// A signal that comes while the thread is not blocked reading the terminal, e.g. while the
+    /// line is refreshed, is only noticed by rustyline once the next one interrupts the read.
+    #[cfg(unix)]
+    fn signal(self: &Arc<Self>, thread: libc::pthread_t) {
+        let shared = self.clone();
+        let resend = move || loop {
+            {
+                let pending = shared.lock();
+                if !matches!(*pending, Some(PendingRead::Interrupted(t)) if t == thread) {
+                    return;
+                }
+                // The lock is held, so the thread has not left `read_line` yet, and SIGINT is
+                // either handled by rustyline or ignored.
+                unsafe { libc::pthread_kill(thread, libc::SIGINT) };
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        let spawned = std::thread::Builder::new()
+            .name("readline4k-interrupt".to_string())
+            .spawn(resend);
+        if spawned.is_err() {
+            // Signals it once at least.
+            unsafe { libc::pthread_kill(thread, libc::SIGINT) };
+        }
+    }
+}
+
+/// How rustyline talks to the terminal, detected the way it does.
+#[cfg(unix)]
+struct TerminalIo {
+    unsupported: bool, // a terminal rustyline cannot drive, whose input is read as is
+    input: bool,
+    output: bool,
+}
+
+#[cfg(unix)]
+impl TerminalIo {
+    fn detect(behavior: Behavior) -> Self {
+        let unsupported = std::env::var("TERM").is_ok_and(|term| {
+            ["dumb", "cons25", "emacs"]
+                .iter()
+                .any(|unsupported| unsupported.eq_ignore_ascii_case(&term))
+        });
+        let prefer_term = behavior == Behavior::PreferTerm
+            && std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/tty")
+                .is_ok();
+        let (input, output) = if prefer_term {
+            (true, true)
+        } else {
+            unsafe { (libc::isatty(0) != 0, libc::isatty(1) != 0) }
+        };
+        Self {
+            unsupported,
+            input,
+            output,
+        }
+    }
+}
+
+/// Clears the pending read when dropped, even if the read panics.
+struct PendingReadGuard<'a> {
+    state: &'a ReadState,
+    #[cfg(unix)]
+    _sigint: Option<IgnoreSigint>, // dropped once the read is no longer pending
+}
+
+impl Drop for PendingReadGuard<'_> {
+    fn drop(&mut self) {
+        *self.state.pending.lock() = None;
+    }
+}
+
+/// Ignores SIGINT until dropped, restoring the previous disposition then.
+///
+/// rustyline saves the disposition when installing its handler and restores it when removing
+/// it. Setting it to `SIG_IGN` also discards a SIGINT sent but not delivered yet. The disposition
+/// is process-wide, so when reads overlap, only the first one changes it and the last one
+/// restores it, see [IGNORED_SIGINT].
+#[cfg(unix)]
+struct IgnoreSigint;
+
+/// The reads ignoring SIGINT, shared by all editors.
+#[cfg(unix)]
+struct IgnoredSigint {
+    count: usize,
+    previous: Option<libc::sigaction>, // the disposition restored by the last read
+}
+
+#[cfg(unix)]
+static IGNORED_SIGINT: Mutex<IgnoredSigint> = Mutex::new(IgnoredSigint {
+    count: 0,
+    previous: None,
+});
+
+#[cfg(unix)]
+impl IgnoreSigint {
+    fn new() -> Option<Self> {
+        let mut ignored = IGNORED_SIGINT
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if ignored.count == 0 {
+            let mut ignore: libc::sigaction = unsafe { std::mem::zeroed() };
+            ignore.sa_sigaction = libc::SIG_IGN;
+            let mut previous: libc::sigaction = unsafe { std::mem::zeroed() };
+            if unsafe { libc::sigaction(libc::SIGINT, &ignore, &mut previous) } != 0 {
+                return None;
+            }
+            ignored.previous = Some(previous);
+        }
+        ignored.count += 1;
+        Some(Self)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for IgnoreSigint {
+    fn drop(&mut self) {
+        let mut ignored = IGNORED_SIGINT
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        ignored.count -= 1;
+        if ignored.count == 0 {
+            if let Some(previous) = ignored.previous.take() {
+                unsafe { libc::sigaction(libc::SIGINT, &previous, null_mut()) };
+            }
+        }
+    }
+}
+
+impl ReadState {
+    /// Mirrors the conditions under which rustyline installs its signal handlers.
+    #[cfg(unix)]
+    fn new(behavior: Behavior) -> Self {
+        let term = TerminalIo::detect(behavior);
+        Self {
+            interruptible: !term.unsupported && term.input && term.output,
+            pending: Default::default(),
+            interrupted: AtomicI32::new(OK),
+            activity: Default::default(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn new(_behavior: Behavior) -> Self {
+        Self {
+            interruptible: false,
+            pending: Default::default(),
+            interrupted: AtomicI32::new(OK),
+            activity: Default::default(),
+        }
+    }
+
+    fn begin(&self) -> PendingReadGuard<'_> {
+        #[cfg(unix)]
+        let (read, sigint) = if self.interruptible {
+            let thread = unsafe { libc::pthread_self() };
+            let read = PendingRead::Starting {
+                thread,
+                interrupt: false,
+            };
+            (read, IgnoreSigint::new())
+        } else {
+            (PendingRead::Blocking, None)
+        };
+        #[cfg(not(unix))]
+        let read = PendingRead::Blocking;
+        self.interrupted.store(OK, Ordering::SeqCst);
+        self.activity.reset();
+        *self.pending.lock() = Some(read);
+        PendingReadGuard {
+            state: self,
+            #[cfg(unix)]
+            _sigint: sigint,
+        }
+    }
+
+    /// Cancels the pending read, returns false if there is none.
+    fn cancel(&self) -> Result<bool, FfiError> {
+        self.interrupt(ERROR_CANCELLED)
+    }
+
+    /// Interrupts the pending read so that it fails with `reason`, returns false if there is none.
+    fn interrupt(&self, reason: c_int) -> Result<bool, FfiError> {
+        let mut pending = self.pending.lock();
+        match &mut *pending {
+            None => Ok(false),
+            Some(PendingRead::Blocking) => Err(FfiError::new(
+                ERROR_UNSUPPORTED,
+                "Only reads from an interactive terminal can be cancelled".to_string(),
+            )),
+            #[cfg(unix)]
+            Some(read) => {
+                self.interrupted.store(reason, Ordering::SeqCst);
+                match *read {
+                    // Signalled by `arm`.
+                    PendingRead::Starting { thread, .. } => {
+                        *read = PendingRead::Starting {
+                            thread,
+                            interrupt: true,
+                        }
+                    }
+                    PendingRead::Interruptible(thread) => {
+                        *read = PendingRead::Interrupted(thread);
+                        self.pending.signal(thread);
+                    }
+                    _ => {}
+                }
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// The terminal of a headless editor, see `new_headless_editor`.
+///
+/// rustyline only talks to the standard input and output, so they are redirected to the slave
+/// side of a pseudo-terminal whenever the editor uses the terminal. Input is fed to, and the
+/// rendered output collected from, the master side.
+#[cfg(unix)]
+struct HeadlessTerm {
+    pty: Arc<Pty>,
+    slave: File,
+}
+
+#[cfg(unix)]
+struct Pty {
+    master: File, // non-blocking
+    output: Mutex<Vec<u8>>,
+}
+
+/// Held while the standard input and output, which are process-wide, are redirected.
+#[cfg(unix)]
+static STDIO: Mutex<()> = Mutex::new(());
+
+/// Serializes the calls to `ptsname`, which returns a static buffer.
+#[cfg(unix)]
+static PTSNAME: Mutex<()> = Mutex::new(());
+
+#[cfg(unix)]
+impl HeadlessTerm {
+    fn open(columns: u16, rows: u16) -> Result<Self, FfiError> {
+        Ok(Self::open_pty(columns, rows).map_err(ReadlineError::Io)?)
+    }
+
+    fn open_pty(columns: u16, rows: u16) -> io::Result<Self> {
+        let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = unsafe { File::from_raw_fd(fd) };
+        if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let name = {
+            let _ptsname = PTSNAME.lock().unwrap_or_else(PoisonError::into_inner);
+            let name = unsafe { libc::ptsname(fd) };
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            unsafe { CStr::from_ptr(name) }.to_owned()
+        };
+        let slave = unsafe { libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY) };
+        if slave < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let slave = unsafe { File::from_raw_fd(slave) };
+        // Raw from the start, so that input fed before a read is neither echoed nor line-edited.
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        let size = libc::winsize {
+            ws_row: rows,
+            ws_col: columns,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        unsafe {
+            if libc::tcgetattr(slave.as_raw_fd(), &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0
+                || libc::ioctl(slave.as_raw_fd(), libc::TIOCSWINSZ, &size) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        let pty = Arc::new(Pty {
+            master,
+            output: Mutex::new(Vec::new()),
+        });
+        // Keeps draining the output, so that the editor never blocks on a full pseudo-terminal.
+        let drain = pty.clone();
+        std::thread::Builder::new()
+            .name("readline4k-headless".to_string())
+            .spawn(move || drain.drain_until_closed())?;
+        Ok(Self { pty, slave })
+    }
+
+    /// Runs `f` with the standard input and output redirected to the pseudo-terminal.
+    ///
+    /// Only one headless editor can use the terminal at a time: this fails with `ERROR_BUSY`
+    /// instead of waiting for another one, whose read may never end.
+    fn redirect<T>(&self, f: impl FnOnce() -> T) -> Result<T, FfiError> {
+        let _stdio = match STDIO.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => {
+                return Err(FfiError::new(
+                    ERROR_BUSY,
+                    "Another headless editor is using the terminal".to_string(),
+                ))
+            }
+        };
+        let _ = io::stdout().flush();
+        let saved = unsafe { [libc::dup(0), libc::dup(1)] };
+        // Restores the standard input and output when dropped, even if `f` panics.
+        struct Restore([c_int; 2]);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                for (fd, saved) in self.0.into_iter().enumerate() {
+                    if saved >= 0 {
+                        unsafe {
+                            libc::dup2(saved, fd as c_int);
+                            libc::close(saved);
+                        }
+                    }
+                }
+            }
+        }
+        let restore = Restore(saved);
+        let slave = self.slave.as_raw_fd();
+        if saved.contains(&-1) || unsafe { libc::dup2(slave, 0) < 0 || libc::dup2(slave, 1) < 0 } {
+            return Err(ReadlineError::Io(io::Error::last_os_error()).into());
+        }
+        let result = f();
+        drop(restore);
+        Ok(result)
+    }
+
+    fn feed(&self, mut input: &[u8]) -> Result<(), FfiError> {
+        while !input.is_empty() {
+            match (&self.pty.master).write(input) {
+                Ok(n) => input = &input[n..],
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    // Waits for the editor to consume some input.
+                    self.pty.wait(libc::POLLOUT);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(ReadlineError::Io(err).into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the output rendered since the last call, keeping an incomplete trailing UTF-8 sequence.
+    fn take_output(&self) -> Result<String, FfiError> {
+        let mut output = self
+            .pty
+            .output
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Reading the master flushes what the editor wrote so far.
+        self.pty.drain(&mut output);
+        let complete = match std::str::from_utf8(&output) {
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            _ => output.len(),
+        };
+        let rest = output.split_off(complete);
+        let taken = std::mem::replace(&mut *output, rest);
+        Ok(String::from_utf8_lossy(&taken).into_owned())
+    }
+}
+
+#[cfg(unix)]
+impl Pty {
+    fn wait(&self, events: libc::c_short) -> libc::c_short {
+        let mut fd = libc::pollfd {
+            fd: self.master.as_raw_fd(),
+            events,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut fd, 1, -1) };
+        fd.revents
+    }
+
+    /// Appends the available output to `output` without blocking, returns false once the
+    /// pseudo-terminal is closed.
+    fn drain(&self, output: &mut Vec<u8>) -> bool {
+        let mut buf = [0u8; 4096];
+        loop {
+            match (&self.master).read(&mut buf) {
+                Ok(0) => return false,
+                Ok(n) => output.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+    }
+
+    fn drain_until_closed(&self) {
+        loop {
+            let revents = self.wait(libc::POLLIN);
+            let mut output = self.output.lock().unwrap_or_else(PoisonError::into_inner);
+            let len = output.len();
+            let open = self.drain(&mut output);
+            // Hung up once the slave side is closed, when the editor is freed.
+            if !open || revents & libc::POLLHUP != 0 && output.len() == len {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct HeadlessTerm;
+
+#[cfg(not(unix))]
+impl HeadlessTerm {
+    fn open(_columns: u16, _rows: u16) -> Result<Self, FfiError> {
+        Err(FfiError::new(
+            ERROR_UNSUPPORTED,
+            "Headless editors are only supported on Unix".to_string(),
+        ))
+    }
+
+    fn redirect<T>(&self, f: impl FnOnce() -> T) -> Result<T, FfiError> {
+        Ok(f())
+    }
+
+    fn feed(&self, _input: &[u8]) -> Result<(), FfiError> {
+        Ok(())
+    }
+
+    fn take_output(&self) -> Result<String, FfiError> {
+        Ok(String::new())
+    }
+}
+
+/// The history of an editor: a plain file (the default), an SQLite database that concurrent
+/// editors can share (with the `sqlite-history` feature), or memory only.
+enum EditorHistory {
+    File(FileHistory),
+    #[cfg(feature = "sqlite-history")]
+    Sqlite(SqliteHistory),
+    Memory(MemHistory),
+    /// A history that stays empty, whatever its configured size.
+    Disabled(MemHistory),
+}
+
+/// Evaluates `$body` with `$h` bound to the history backend in use.
+macro_rules! with_backend {
+    ($history:expr, $h:ident => $body:expr) => {
+        match $history {
+            EditorHistory::File($h) => $body,
+            #[cfg(feature = "sqlite-history")]
+            EditorHistory::Sqlite($h) => $body,
+            EditorHistory::Memory($h) | EditorHistory::Disabled($h) => $body,
+        }
+    };
+}
+
+impl EditorHistory {
+    fn new(cfg: &EditorConfig, config: &Config) -> Result<Self, FfiError> {
+        match cfg.history_backend {
+            0 => Ok(Self::File(FileHistory::with_config(config))),
+            1 => Self::sqlite(cfg.history_path, cfg.history_path_len, config),
+            2 => Ok(Self::Memory(MemHistory::with_config(config))),
+            3 => Ok(Self::disabled()?),
+            v => Err(FfiError::invalid_enum("history_backend", v)),
+        }
+    }
+
+    /// A history that never keeps any entry.
+    fn disabled() -> rustyline::Result<Self> {
+        let mut history = MemHistory::new();
+        history.set_max_len(0)?;
+        Ok(Self::Disabled(history))
+    }
+
+    #[cfg(feature = "sqlite-history")]
+    fn sqlite(path: *const u8, path_len: c_int, config: &Config) -> Result<Self, FfiError> {
+        let path = buf_to_str(path, path_len)?;
+        let path = (!path.is_empty()).then(|| Path::new(path));
+        let history = match path {
+            None => SQLiteHistory::with_config(config)?,
+            Some(path) => SQLiteHistory::open(config, path)?,
+        };
+        Ok(Self::Sqlite(SqliteHistory {
+            history,
+            path: path.and_then(SqliteHistory::normalize),
+            last_added: None,
+        }))
+    }
+
+    #[cfg(not(feature = "sqlite-history"))]
+    fn sqlite(_path: *const u8, _path_len: c_int, _config: &Config) -> Result<Self, FfiError> {
+        Err(FfiError::new(
+            ERROR_UNSUPPORTED,
+            "The SQLite history requires the sqlite-history feature".to_string(),
+        ))
+    }
+
+    /// Returns the entry at `index` with its session ID and timestamp (millis since the epoch).
+    #[cfg_attr(not(feature = "sqlite-history"), allow(unused_variables))]
+    fn details(&self, index: usize) -> Result<Option<(String, i64, i64)>, FfiError> {
+        match self {
+            #[cfg(feature = "sqlite-history")]
+            Self::Sqlite(h) => h.details(index),
+            _ => Err(Self::no_details()),
+        }
+    }
+
+    /// Returns the ID of the session the entries added by this editor belong to, 0 if none yet.
+    fn session_id(&self) -> Result<i64, FfiError> {
+        match self {
+            #[cfg(feature = "sqlite-history")]
+            Self::Sqlite(h) => h.session_id(),
+            _ => Err(Self::no_details()),
+        }
+    }
+
+    /// Replaces the entries in memory with the ones of the history file at `path`, including
+    /// those appended by other editors. An SQLite history is always up to date.
+    fn reload(&mut self, path: &Path, config: &Config) -> Result<(), FfiError> {
+        match self {
+            Self::File(h) if path.exists() => {
+                // Loaded apart, so that a failure keeps the current entries.
+                let mut history = FileHistory::with_config(config);
+                history.load(path)?;
+                *h = history;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the `history_backend` code and the database of an SQLite history stored in a file.
+    fn backend(&self) -> (c_int, Option<String>) {
+        match self {
+            Self::File(_) => (0, None),
+            #[cfg(feature = "sqlite-history")]
+            Self::Sqlite(h) => (1, h.path.as_ref().map(|p| p.to_string_lossy().into_owned())),
+            Self::Memory(_) => (2, None),
+            Self::Disabled(_) => (3, None),
+        }
+    }
+
+    /// Fails for a history that must never be read from or written to a file.
+    fn check_persistent(&self) -> Result<(), FfiError> {
+        match self {
+            Self::Memory(_) | Self::Disabled(_) => Err(FfiError::new(
+                ERROR_UNSUPPORTED,
+                "The history is kept in memory only".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn no_details() -> FfiError {
+        FfiError::new(
+            ERROR_UNSUPPORTED,
+            "Sessions and timestamps are only recorded by an SQLite history stored in a file"
+                .to_string(),
+        )
+    }
+}
+
+impl History for EditorHistory {
+    fn get(
+        &self,
+        index: usize,
+        dir: SearchDirection,
+    ) -> rustyline::Result<Option<SearchResult<'_>>> {
+        with_backend!(self, h => h.get(index, dir))
+    }
+
+    fn add(&mut self, line: &str) -> rustyline::Result<bool> {
+        with_backend!(self, h => h.add(line))
+    }
+
+    fn add_owned(&mut self, line: String) -> rustyline::Result<bool> {
+        with_backend!(self, h => h.add_owned(line))
+    }
+
+    fn len(&self) -> usize {
+        with_backend!(self, h => h.len())
+    }
+
+    fn is_empty(&self) -> bool {
+        with_backend!(self, h => h.is_empty())
+    }
+
+    fn set_max_len(&mut self, len: usize) -> rustyline::Result<()> {
+        match self {
+            Self::Disabled(_) => Ok(()),
+            _ => with_backend!(self, h => h.set_max_len(len)),
+        }
+    }
+
+    fn ignore_dups(&mut self, yes: bool) -> rustyline::Result<()> {
+        with_backend!(self, h => h.ignore_dups(yes))
+    }
+
+    fn ignore_space(&mut self, yes: bool) {
+        with_backend!(self, h => h.ignore_space(yes))
+    }
+
+    fn save(&mut self, path: &Path) -> rustyline::Result<()> {
+        with_backend!(self, h => h.save(path))
+    }
+
+    fn append(&mut self, path: &Path) -> rustyline::Result<()> {
+        with_backend!(self, h => h.append(path))
+    }
+
+    fn load(&mut self, path: &Path) -> rustyline::Result<()> {
+        with_backend!(self, h => h.load(path))
+    }
+
+    fn clear(&mut self) -> rustyline::Result<()> {
+        with_backend!(self, h => h.clear())
+    }
+
+    fn search(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+    ) -> rustyline::Result<Option<SearchResult<'_>>> {
+        with_backend!(self, h => h.search(term, start, dir))
+    }
+
+    fn starts_with(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+    ) -> rustyline::Result<Option<SearchResult<'_>>> {
+        with_backend!(self, h => h.starts_with(term, start, dir))
+    }
+}
+
+/// An [SQLiteHistory] that keeps track of its database and of the session of this editor, so
+/// that the details rustyline records (session, timestamp) can be queried.
+#[cfg(feature = "sqlite-history")]
+struct SqliteHistory {
+    history: SQLiteHistory,
+    path: Option<PathBuf>,     // None means in memory
+    last_added: Option<usize>, // index of the last entry added by this editor
+}
+
+#[cfg(feature = "sqlite-history")]
+impl SqliteHistory {
+    /// Julian day of the Unix epoch, timestamps are stored as Julian days.
+    const UNIX_EPOCH_JULIAN_DAY: f64 = 2440587.5;
+
+    /// Returns the database at `path` as rustyline opens it, None for an in-memory database.
+    fn normalize(path: &Path) -> Option<PathBuf> {
+        let path = path.as_os_str();
+        (!path.is_empty() && path != ":memory:").then(|| PathBuf::from(path))
+    }
+
+    /// Opens a separate connection, the one of rustyline being private.
+    fn connect(&self) -> Result<rusqlite::Connection, FfiError> {
+        let Some(path) = &self.path else {
+            return Err(EditorHistory::no_details());
+        };
+        let flags = rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY;
+        rusqlite::Connection::open_with_flags(path, flags)
+            .map_err(|err| FfiError::from(ReadlineError::from(err)))
+    }
+
+    fn details(&self, index: usize) -> Result<Option<(String, i64, i64)>, FfiError> {
+        let conn = self.connect()?;
+        let query = "SELECT entry, session_id, \
+                     CAST(round((timestamp - ?2) * 86400000) AS INTEGER) \
+                     FROM history WHERE rowid = ?1;";
+        let rowid = index as i64 + 1; // first rowid is 1
+        conn.query_row(query, (rowid, Self::UNIX_EPOCH_JULIAN_DAY), |r| {
+            Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+        })
+        .optional()
+        .map_err(|err| FfiError::from(ReadlineError::from(err)))
+    }
+
+    fn session_id(&self) -> Result<i64, FfiError> {
+        let conn = self.connect()?;
+        let Some(index) = self.last_added else {
+            return Ok(0);
+        };
+        let query = "SELECT session_id FROM history WHERE rowid = ?1;";
+        let session_id = conn
+            .query_row(query, [index as i64 + 1], |r| r.get(0))
+            .optional()
+            .map_err(|err| FfiError::from(ReadlineError::from(err)))?;
+        Ok(session_id.unwrap_or(0))
+    }
+
+    /// Follows a switch to the database at `path`, which starts a new session.
+    fn switch_to(&mut self, path: &Path) {
+        let path = Self::normalize(path);
+        if path != self.path {
+            self.path = path;
+            self.last_added = None;
+        }
+    }
+}
+
+#[cfg(feature = "sqlite-history")]
+impl History for SqliteHistory {
+    fn get(
+        &self,
+        index: usize,
+        dir: SearchDirection,
+    ) -> rustyline::Result<Option<SearchResult<'_>>> {
+        self.history.get(index, dir)
+    }
+
+    fn add(&mut self, line: &str) -> rustyline::Result<bool> {
+        let added = self.history.add(line)?;
+        if added {
+            // The length is the rowid of the entry just added.
+            self.last_added = Some(self.history.len() - 1);
+        }
+        Ok(added)
+    }
+
+    fn add_owned(&mut self, line: String) -> rustyline::Result<bool> {
+        self.add(&line)
+    }
+
+    fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    fn set_max_len(&mut self, len: usize) -> rustyline::Result<()> {
+        self.history.set_max_len(len)
+    }
+
+    fn ignore_dups(&mut self, yes: bool) -> rustyline::Result<()> {
+        self.history.ignore_dups(yes)
+    }
+
+    fn ignore_space(&mut self, yes: bool) {
+        self.history.ignore_space(yes)
+    }
+
+    fn save(&mut self, path: &Path) -> rustyline::Result<()> {
+        // Saving elsewhere backs up the database but keeps using it.
+        self.history.save(path)
+    }
+
+    fn append(&mut self, path: &Path) -> rustyline::Result<()> {
+        self.history.append(path)?;
+        self.switch_to(path);
+        Ok(())
+    }
+
+    fn load(&mut self, path: &Path) -> rustyline::Result<()> {
+        self.history.load(path)?;
+        self.switch_to(path);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> rustyline::Result<()> {
+        self.history.clear()?;
+        if self.path.is_none() {
+            // The entries of the session are deleted from an in-memory database only.
+            self.last_added = None;
+        }
+        Ok(())
+    }
+
+    fn search(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+    ) -> rustyline::Result<Option<SearchResult<'_>>> {
+        self.history.search(term, start, dir)
+    }
+
+    fn starts_with(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+    ) -> rustyline::Result<Option<SearchResult<'_>>> {
+        self.history.starts_with(term, start, dir)
+    }
+}
+
+/// A read running on its own thread, see `editor_read_line_async`.
+struct ReadRequest {
+    editor: Arc<EditorEntry>,
+    cancelled: AtomicBool,
+    result: Mutex<Option<Result<String, FfiError>>>,
+    done: Condvar,
+}
+
+impl ReadRequest {
+    fn run(&self, prefix: String) {
+        let read = || {
+            self.editor.read_line(|rl| {
+                // Checked once the read is pending, so a concurrent `cancel` cannot be missed.
+                if self.cancelled.load(Ordering::SeqCst) {
+                    return Err(ReadlineError::Interrupted);
+                }
+                rl.readline(&prefix)
+            })
+        };
+        let result = match panic::catch_unwind(AssertUnwindSafe(read)) {
+            Ok(Err(err))
+                if err.error == ERROR_INTERRUPTED && self.cancelled.load(Ordering::SeqCst) =>
+            {
+                Err(FfiError::cancelled())
+            }
+            Ok(result) => result,
+            Err(payload) => Err(FfiError::panic(payload)),
+        };
+        *self.result.lock().unwrap_or_else(PoisonError::into_inner) = Some(result);
+        self.done.notify_all();
+    }
+
+    /// Cancels the read, returns false if it already completed.
+    fn cancel(&self) -> Result<bool, FfiError> {
+        if self
+            .result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+        {
+            return Ok(false);
+        }
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.editor.read.cancel()?;
+        Ok(true)
+    }
+}
+
+fn read_request(request: ReadRequestHandle) -> Result<Arc<ReadRequest>, FfiError> {
+    lock_registry(&READ_REQUESTS)
+        .get(request)
+        .ok_or_else(|| FfiError::disposed("Read request"))
+}
+
+/// An error reported to the caller through [ReadLineResult].
+#[derive(Clone)]
+struct FfiError {
+    error: c_int,
+    os_error: c_int,
+    message: String,
+}
+
+impl FfiError {
+    fn new(error: c_int, message: String) -> Self {
+        Self {
+            error,
+            os_error: 0,
+            message,
+        }
+    }
+
+    fn invalid_argument(message: &str) -> Self {
+        Self::new(ERROR_INVALID_ARGUMENT, message.to_string())
+    }
+
+    fn cancelled() -> Self {
+        Self::interrupted(ERROR_CANCELLED)
+    }
+
+    fn interrupted(reason: c_int) -> Self {
+        let message = match reason {
+            ERROR_TIMEOUT => "Read timed out",
+            _ => "Read cancelled",
+        };
+        Self::new(reason, message.to_string())
+    }
+
+    fn disposed(what: &str) -> Self {
+        Self::new(ERROR_DISPOSED, format!("{} is disposed", what))
+    }
+
+    fn invalid_enum(name: &str, value: c_int) -> Self {
+        Self::new(
+            ERROR_INVALID_ARGUMENT,
+            format!("Invalid {} value: {}", name, value),
+        )
+    }
+
+    fn panic(payload: Box<dyn Any + Send>) -> Self {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown cause".to_string()
+        };
+        Self::new(ERROR_PANIC, format!("Native panic: {}", message))
+    }
+
+    fn into_result(self) -> ReadLineResult {
+        ReadLineResult {
+            error: self.error,
+            error_message: to_c_string(&self.message).into_raw(),
+            os_error: self.os_error,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<ReadlineError> for FfiError {
+    fn from(err: ReadlineError) -> Self {
+        let (error, os_error, message) = match err {
+            ReadlineError::Eof => (ERROR_EOF, 0, "Reached end of file".to_string()),
+            ReadlineError::Interrupted => (
+                ERROR_INTERRUPTED,
+                0,
+                "Received interrupt signal".to_string(),
+            ),
+            ReadlineError::Io(err) if err.kind() == std::io::ErrorKind::InvalidData => (
+                ERROR_DECODE,
+                err.raw_os_error().unwrap_or(0),
+                format!("Decode error: {}", err),
+            ),
+            ReadlineError::Io(err) => (
+                ERROR_IO,
+                err.raw_os_error().unwrap_or(0),
+                format!("I/O error: {}", err),
+            ),
+            #[cfg(unix)]
+            ReadlineError::Errno(errno) => (
+                ERROR_ERRNO,
+                errno as c_int,
+                format!("System call error: {}", errno),
+            ),
+            ReadlineError::Signal(rustyline::error::Signal::Resize) => (
+                ERROR_WINDOW_RESIZED,
+                0,
+                "Terminal window resized".to_string(),
+            ),
+            ReadlineError::Signal(signal) => {
+                (ERROR_SIGNAL, 0, format!("Received signal: {:?}", signal))
+            }
+            #[cfg(windows)]
+            ReadlineError::Decode(err) => (ERROR_DECODE, 0, format!("Decode error: {}", err)),
+            #[cfg(windows)]
+            ReadlineError::SystemError(err) => (ERROR_SYSTEM, 0, format!("System error: {}", err)),
+            #[cfg(feature = "sqlite-history")]
+            ReadlineError::SQLiteError(err) => (ERROR_IO, 0, format!("SQLite error: {}", err)),
+            err => (ERROR_UNKNOWN, 0, format!("Unknown error: {:?}", err)),
+        };
+        Self {
+            error,
+            os_error,
+            message,
+        }
+    }
+}
+
+/// A value that can be returned in [ReadLineResult::result].
+trait ResultValue {
+    fn into_value(self) -> Option<String>;
+}
+
+impl ResultValue for () {
+    fn into_value(self) -> Option<String> {
+        None
+    }
+}
+
+impl ResultValue for String {
+    fn into_value(self) -> Option<String> {
+        Some(self)
+    }
+}
+
+impl ResultValue for Option<String> {
+    fn into_value(self) -> Option<String> {
+        self
+    }
+}
+
+/// Runs `f` and converts its outcome, including a panic, into a leaked [ReadLineResult].
+fn ffi_result<T, F>(f: F) -> *mut ReadLineResult
+where
+    T: ResultValue,
+    F: FnOnce() -> Result<T, FfiError>,
+{
+    let result = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => match value.into_value().map(to_result_buffer) {
+            None => ReadLineResult::default(),
+            Some(Ok((result, result_len))) => ReadLineResult {
+                result,
+                result_len,
+                ..Default::default()
+            },
+            Some(Err(err)) => err.into_result(),
+        },
+        Ok(Err(err)) => err.into_result(),
+        Err(payload) => FfiError::panic(payload).into_result(),
+    };
+    result.leak()
+}
+
+/// Moves `value` to the buffer of a [ReadLineResult], returned with its length.
+fn to_result_buffer(value: String) -> Result<(*mut c_char, c_int), FfiError> {
+    let len =
+        c_int::try_from(value.len()).map_err(|_| FfiError::invalid_argument("Result too large"))?;
+    let mut bytes = value.into_bytes();
+    bytes.push(0);
+    Ok((Box::into_raw(bytes.into_boxed_slice()) as *mut c_char, len))
+}
+
+/// Overwrites `bytes` with zeros, without the compiler eliding it as a dead store.
+fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Runs `f`, returning `default` if it panics.
+fn catch_or<T, F: FnOnce() -> T>(default: T, f: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(default)
+}
+
+fn editor(rl: EditorHandle) -> Result<Arc<EditorEntry>, FfiError> {
+    lock_registry(&EDITORS)
+        .get(rl)
+        .ok_or_else(|| FfiError::disposed("Editor"))
+}
+
+/// Runs `f` on the helper of the editor, failing if the editor is in use.
+fn update_helper<F: FnOnce(&mut CustomHelper)>(rl: EditorHandle, f: F) -> *mut ReadLineResult {
+    ffi_result(|| {
+        let editor = editor(rl)?;
+        if let Some(h) = editor.lock()?.helper_mut() {
+            f(h);
+        }
+        Ok(())
+    })
+}
+
+/// Borrows the value at `ptr`, failing if it is null.
+fn borrow_in<'a, T>(ptr: *const T) -> Result<&'a T, FfiError> {
+    if ptr.is_null() {
+        return Err(FfiError::invalid_argument("Unexpected null pointer"));
+    }
+    Ok(unsafe { &*ptr })
+}
+
+fn write_out<T>(ptr: *mut T, value: T) {
+    if !ptr.is_null() {
+        unsafe { *ptr = value };
+    }
+}
+
+fn take_search_result(
+    result: Option<SearchResult<'_>>,
+    out_idx: *mut c_int,
+    out_pos: *mut c_int,
+) -> Option<String> {
+    let found = result?;
+    write_out(out_idx, found.idx as c_int);
+    write_out(out_pos, found.pos as c_int);
+    Some(found.entry.into_owned())
+}
+
+fn map_config(cfg: &EditorConfig) -> Result<Config, FfiError> {
+    let history_dupes = map_history_duplicates(cfg.history_duplicates)?;
+    let completion_type = map_completion_type(cfg.completion_type)?;
+    let edit_mode = map_edit_mode(cfg.edit_mode)?;
+    let bell_style = map_bell_style(cfg.bell_style)?;
+    let color_mode = map_color_mode(cfg.color_mode)?;
+    let behavior = match cfg.behavior {
+        0 => Behavior::Stdio,
+        1 => Behavior::PreferTerm,
+        v => return Err(FfiError::invalid_enum("behavior", v)),
+    };
+    let keyseq_timeout = map_keyseq_timeout(cfg.key_seq_timeout)?;
+
+    let mut builder = Config::builder();
+    builder = builder.max_history_size(map_size("max_history_size", cfg.max_history_size)?)?;
+    // Fallback to boolean API for duplicates handling
+    builder =
+        builder.history_ignore_dups(!matches!(history_dupes, HistoryDuplicates::AlwaysAdd))?;
+    builder = builder.history_ignore_space(cfg.history_ignore_space);
+    builder = builder.completion_type(completion_type);
+    builder = builder.completion_show_all_if_ambiguous(cfg.completion_show_all_if_ambiguous);
+    builder = builder.completion_prompt_limit(map_size(
+        "completion_prompt_limit",
+        cfg.completion_prompt_limit,
+    )?);
+    builder = builder.keyseq_timeout(keyseq_timeout);
+    builder = builder.edit_mode(edit_mode);
+    builder = builder.auto_add_history(cfg.auto_add_history);
+    builder = builder.bell_style(bell_style);
+    builder = builder.color_mode(color_mode);
+    builder = builder.behavior(behavior);
+    builder = builder.tab_stop(cfg.tab_stop);
+    builder = builder.indent_size(cfg.indent_size);
+    builder = builder.check_cursor_position(cfg.check_cursor_position);
+    builder = builder.bracketed_paste(cfg.enable_bracketed_paste);
+    builder.enable_synchronized_output(cfg.enable_synchronized_output);
+    builder = builder.enable_signals(cfg.enable_signals);
+    Ok(builder.build())
+}
+
+/// The reverse of [map_config], for the configuration of a live editor.
+fn unmap_config(config: &Config, history_backend: c_int) -> EditorConfig {
+    EditorConfig {
+        max_history_size: config.max_history_size().min(i32::MAX as usize) as i32,
+        history_duplicates: match config.history_duplicates() {
+            HistoryDuplicates::AlwaysAdd => 0,
+            HistoryDuplicates::IgnoreConsecutive => 1,
+        },
+        history_ignore_space: config.history_ignore_space(),
+        completion_type: match config.completion_type() {
+            CompletionType::List => 1,
+            _ => 0, // Circular
+        },
+        completion_show_all_if_ambiguous: config.completion_show_all_if_ambiguous(),
+        completion_prompt_limit: config.completion_prompt_limit().min(i32::MAX as usize) as i32,
+        key_seq_timeout: config.keyseq_timeout().map_or(-1, i32::from),
+        edit_mode: match config.edit_mode() {
+            EditMode::Vi => 1,
+            _ => 0, // Emacs
+        },
+        auto_add_history: config.auto_add_history(),
+        bell_style: match config.bell_style() {
+            BellStyle::Audible => 0,
+            BellStyle::None => 1,
+            BellStyle::Visible => 2,
+        },
+        color_mode: match config.color_mode() {
+            ColorMode::Forced => 1,
+            ColorMode::Disabled => 2,
+            _ => 0, // Enabled
+        },
+        behavior: match config.behavior() {
+            Behavior::PreferTerm => 1,
+            _ => 0, // Stdio
+        },
+        tab_stop: config.tab_stop(),
+        indent_size: config.indent_size(),
+        check_cursor_position: config.check_cursor_position(),
+        enable_bracketed_paste: config.enable_bracketed_paste(),
+        enable_synchronized_output: config.enable_synchronized_output(),
+        enable_signals: config.enable_signals(),
+        history_backend,
+        history_path: std::ptr::null(),
+        history_path_len: 0,
+    }
+}
+
+fn map_history_duplicates(value: c_int) -> Result<HistoryDuplicates, FfiError> {
+    match value {
+        0 => Ok(HistoryDuplicates::AlwaysAdd),
+        1 => Ok(HistoryDuplicates::IgnoreConsecutive),
+        v => Err(FfiError::invalid_enum("history_duplicates", v)),
+    }
+}
+
+fn map_completion_type(value: c_int) -> Result<CompletionType, FfiError> {
+    match value {
+        0 => Ok(CompletionType::Circular),
+        1 => Ok(CompletionType::List),
+        v => Err(FfiError::invalid_enum("completion_type", v)),
+    }
+}
+
+fn map_edit_mode(value: c_int) -> Result<EditMode, FfiError> {
+    match value {
+        0 => Ok(EditMode::Emacs),
+        1 => Ok(EditMode::Vi),
+        v => Err(FfiError::invalid_enum("edit_mode", v)),
+    }
+}
+
+fn map_bell_style(value: c_int) -> Result<BellStyle, FfiError> {
+    match value {
+        0 => Ok(BellStyle::Audible),
+        1 => Ok(BellStyle::None),
+        2 => Ok(BellStyle::Visible),
+        v => Err(FfiError::invalid_enum("bell_style", v)),
+    }
+}
+
+/// -1 means no timeout.
+fn map_keyseq_timeout(millis: c_int) -> Result<Option<u16>, FfiError> {
+    match millis {
+        -1 => Ok(None),
+        millis => u16::try_from(millis).map(Some).map_err(|_| {
+            FfiError::new(
+                ERROR_INVALID_ARGUMENT,
+                format!("Invalid key_seq_timeout value: {}", millis),
+            )
+        }),
+    }
+}
+
+/// A number of entries, which cannot be negative.
+fn map_size(name: &str, value: c_int) -> Result<usize, FfiError> {
+    usize::try_from(value).map_err(|_| {
+        FfiError::new(
+            ERROR_INVALID_ARGUMENT,
+            format!("Invalid {} value: {}", name, value),
+        )
+    })
+}
+
+fn map_color_mode(value: c_int) -> Result<ColorMode, FfiError> {
+    match value {
+        0 => Ok(ColorMode::Enabled),
+        1 => Ok(ColorMode::Forced),
+        2 => Ok(ColorMode::Disabled),
+        v => Err(FfiError::invalid_enum("color_mode", v)),
+    }
+}
+
+fn map_search_direction(direction: c_int) -> Result<SearchDirection, FfiError> {
+    match direction {
+        0 => Ok(SearchDirection::Forward),
+        1 => Ok(SearchDirection::Reverse),
+        v => Err(FfiError::invalid_enum("search direction", v)),
+    }
+}
+
+fn map_key_sequence(keys: *const EditorKeyEvent, keys_len: c_int) -> Result<Event, FfiError> {
+    if keys.is_null() || keys_len <= 0 {
+        return Err(FfiError::invalid_argument("Empty key sequence"));
+    }
+    let keys = unsafe { std::slice::from_raw_parts(keys, keys_len as usize) };
+    let keys = keys.iter().map(map_key_event).collect::<Result<_, _>>()?;
+    Ok(Event::KeySeq(keys))
+}
+
+fn map_key_event(key: &EditorKeyEvent) -> Result<KeyEvent, FfiError> {
+    let code = match key.code {
+        0 => KeyCode::Char(map_char(key.ch)?),
+        1 => KeyCode::Backspace,
+        2 => KeyCode::BackTab,
+        3 => KeyCode::Delete,
+        4 => KeyCode::Down,
+        5 => KeyCode::End,
+        6 => KeyCode::Enter,
+        7 => KeyCode::Esc,
+        8 => match u8::try_from(key.ch) {
+            Ok(n @ 1..) => KeyCode::F(n),
+            _ => {
+                return Err(FfiError::new(
+                    ERROR_INVALID_ARGUMENT,
+                    format!("Invalid function key number: {}", key.ch),
+                ))
+            }
+        },
+        9 => KeyCode::Home,
+        10 => KeyCode::Insert,
+        11 => KeyCode::Left,
+        12 => KeyCode::PageDown,
+        13 => KeyCode::PageUp,
+        14 => KeyCode::Right,
+        15 => KeyCode::Tab,
+        16 => KeyCode::Up,
+        v => return Err(FfiError::invalid_enum("key code", v)),
+    };
+    let mut modifiers = Modifiers::NONE;
+    if key.modifiers & 1 != 0 {
+        modifiers |= Modifiers::SHIFT;
+    }
+    if key.modifiers & 2 != 0 {
+        modifiers |= Modifiers::ALT;
+    }
+    if key.modifiers & 4 != 0 {
+        modifiers |= Modifiers::CTRL;
+    }
+    Ok(KeyEvent(code, modifiers))
+}
+
+/// The reverse of [map_key_event].
+fn unmap_key_event(key: KeyEvent) -> Result<EditorKeyEvent, FfiError> {
+    let KeyEvent(code, modifiers) = key;
+    let (code, ch) = match code {
+        KeyCode::Char(c) => (0, c as u32),
+        KeyCode::Backspace => (1, 0),
+        KeyCode::BackTab => (2, 0),
+        KeyCode::Delete => (3, 0),
+        KeyCode::Down => (4, 0),
+        KeyCode::End => (5, 0),
+        KeyCode::Enter => (6, 0),
+        KeyCode::Esc => (7, 0),
+        KeyCode::F(n) => (8, n as u32),
+        KeyCode::Home => (9, 0),
+        KeyCode::Insert => (10, 0),
+        KeyCode::Left => (11, 0),
+        KeyCode::PageDown => (12, 0),
+        KeyCode::PageUp => (13, 0),
+        KeyCode::Right => (14, 0),
+        KeyCode::Tab => (15, 0),
+        KeyCode::Up => (16, 0),
+        code => {
+            return Err(FfiError::new(
+                ERROR_INVALID_ARGUMENT,
+                format!("Unsupported key: {:?}", code),
+            ))
+        }
+    };
+    let mut bits = 0;
+    if modifiers.contains(Modifiers::SHIFT) {
+        bits |= 1;
+    }
+    if modifiers.contains(Modifiers::ALT) {
+        bits |= 2;
+    }
+    if modifiers.contains(Modifiers::CTRL) {
+        bits |= 4;
+    }
+    Ok(EditorKeyEvent {
+        code,
+        ch,
+        modifiers: bits,
+    })
+}
+
+/// Encodes `key` the way an xterm-compatible terminal sends it.
+fn encode_key(key: KeyEvent, out: &mut Vec<u8>) -> Result<(), FfiError> {
+    let KeyEvent(code, modifiers) = key;
+    let unsupported = || FfiError::invalid_argument(&format!("Cannot encode key {key:?}"));
+    // Modifier parameter of the CSI sequences: 1 + Shift (1) + Alt (2) + Ctrl (4)
+    let mut param = 1;
+    for (modifier, bit) in [
+        (Modifiers::SHIFT, 1),
+        (Modifiers::ALT, 2),
+        (Modifiers::CTRL, 4),
+    ] {
+        if modifiers.contains(modifier) {
+            param += bit;
+        }
+    }
+    let csi = |out: &mut Vec<u8>, number: u8, end: char| match (param, number) {
+        (1, 1) => out.extend(format!("\x1b[{end}").bytes()),
+        (1, _) => out.extend(format!("\x1b[{number}{end}").bytes()),
+        _ => out.extend(format!("\x1b[{number};{param}{end}").bytes()),
+    };
+    match code {
+        KeyCode::Up => csi(out, 1, 'A'),
+        KeyCode::Down => csi(out, 1, 'B'),
+        KeyCode::Right => csi(out, 1, 'C'),
+        KeyCode::Left => csi(out, 1, 'D'),
+        KeyCode::Home => csi(out, 1, 'H'),
+        KeyCode::End => csi(out, 1, 'F'),
+        KeyCode::Insert => csi(out, 2, '~'),
+        KeyCode::Delete => csi(out, 3, '~'),
+        KeyCode::PageUp => csi(out, 5, '~'),
+        KeyCode::PageDown => csi(out, 6, '~'),
+        KeyCode::F(n @ 1..=4) if param == 1 => out.extend([0x1b, b'O', b'O' + n]),
+        KeyCode::F(n @ 1..=4) => csi(out, 1, (b'O' + n) as char),
+        KeyCode::F(n @ 5..=12) => csi(out, [15, 17, 18, 19, 20, 21, 23, 24][n as usize - 5], '~'),
+        KeyCode::BackTab => out.extend(b"\x1b[Z"),
+        KeyCode::Tab if modifiers == Modifiers::SHIFT => out.extend(b"\x1b[Z"),
+        _ => {
+            let byte = match code {
+                KeyCode::Backspace => Some(0x7f),
+                KeyCode::Enter => Some(b'\r'),
+                KeyCode::Esc => Some(0x1b),
+                KeyCode::Tab => Some(b'\t'),
+                _ => None,
+            };
+            // The Alt modifier is sent as an Escape prefix.
+            if modifiers.contains(Modifiers::ALT) {
+                out.push(0x1b);
+            }
+            match (code, byte) {
+                (_, Some(byte)) if !modifiers.intersects(Modifiers::CTRL) => out.push(byte),
+                (KeyCode::Char(c), None) if modifiers.contains(Modifiers::CTRL) => match c {
+                    '?' => out.push(0x7f),
+                    '@'..='_' | 'a'..='z' => out.push(c.to_ascii_uppercase() as u8 & 0x1f),
+                    _ => return Err(unsupported()),
+                },
+                // Shift is only sent as the shifted character, which is known for letters.
+                (KeyCode::Char(c), None) if modifiers.contains(Modifiers::SHIFT) => {
+                    if !c.is_ascii_alphabetic() {
+                        return Err(unsupported());
+                    }
+                    out.push(c.to_ascii_uppercase() as u8)
+                }
+                (KeyCode::Char(c), None) => {
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+                }
+                _ => return Err(unsupported()),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn map_movement(movement: c_int, repeat: RepeatCount) -> Result<Movement, FfiError> {
+    let movement = match movement {
+        0 => Movement::WholeLine,
+        1 => Movement::BeginningOfLine,
+        2 => Movement::EndOfLine,
+        3 => Movement::BackwardWord(repeat, Word::Emacs),
+        4 => Movement::ForwardWord(repeat, At::AfterEnd, Word::Emacs),
+        5 => Movement::BackwardChar(repeat),
+        6 => Movement::ForwardChar(repeat),
+        7 => Movement::LineUp(repeat),
+        8 => Movement::LineDown(repeat),
+        9 => Movement::WholeBuffer,
+        10 => Movement::BeginningOfBuffer,
+        11 => Movement::EndOfBuffer,
+        12 => Movement::ViFirstPrint,
+        v => return Err(FfiError::invalid_enum("movement", v)),
+    };
+    Ok(movement)
+}
+
+fn map_command(cmd: &EditorCommand) -> Result<Cmd, FfiError> {
+    let repeat = cmd.repeat.max(1);
+    let cmd = match cmd.kind {
+        0 => Cmd::Noop,
+        1 => Cmd::Abort,
+        2 => Cmd::AcceptLine,
+        3 => Cmd::AcceptOrInsertLine {
+            accept_in_the_middle: cmd.flag,
+        },
+        4 => Cmd::BeginningOfHistory,
+        5 => Cmd::EndOfHistory,
+        6 => Cmd::CapitalizeWord,
+        7 => Cmd::DowncaseWord,
+        8 => Cmd::UpcaseWord,
+        9 => Cmd::ClearScreen,
+        10 => Cmd::Complete,
+        11 => Cmd::CompleteBackward,
+        12 => Cmd::CompleteHint,
+        13 => Cmd::EndOfFile,
+        14 => Cmd::ForwardSearchHistory,
+        15 => Cmd::ReverseSearchHistory,
+        16 => Cmd::HistorySearchBackward,
+        17 => Cmd::HistorySearchForward,
+        18 => Cmd::Insert(repeat, buf_to_str(cmd.text, cmd.text_len)?.to_string()),
+        19 => Cmd::Interrupt,
+        20 => Cmd::Kill(map_movement(cmd.movement, repeat)?),
+        21 => Cmd::Move(map_movement(cmd.movement, repeat)?),
+        22 => Cmd::NextHistory,
+        23 => Cmd::PreviousHistory,
+        24 => Cmd::Newline,
+        25 => Cmd::QuotedInsert,
+        26 => Cmd::Repaint,
+        27 => Cmd::SelfInsert(repeat, map_char(cmd.ch)?),
+        28 => Cmd::Suspend,
+        29 => Cmd::TransposeChars,
+        30 => Cmd::TransposeWords(repeat),
+        31 => Cmd::Undo(repeat),
+        32 => Cmd::Yank(
+            repeat,
+            if cmd.flag {
+                Anchor::Before
+            } else {
+                Anchor::After
+            },
+        ),
+        33 => Cmd::YankPop,
+        34 => Cmd::Indent(map_movement(cmd.movement, repeat)?),
+        35 => Cmd::Dedent(map_movement(cmd.movement, repeat)?),
+        36 => Cmd::LineUpOrPreviousHistory(repeat),
+        37 => Cmd::LineDownOrNextHistory(repeat),
+        v => return Err(FfiError::invalid_enum("command kind", v)),
+    };
+    Ok(cmd)
+}
+
+fn map_char(ch: u32) -> Result<char, FfiError> {
+    char::from_u32(ch).ok_or_else(|| {
+        FfiError::new(
+            ERROR_INVALID_ARGUMENT,
+            format!("Invalid char value: {}", ch),
+        )
+    })
+}
+
+/// Strings allocated by `readline4k_alloc_string` are preceded by their length, so that they are
+/// freed even if a callback reports another one.
+const STRING_HEADER: usize = std::mem::size_of::<usize>();
+
+fn string_layout(len: usize) -> std::alloc::Layout {
+    std::alloc::Layout::from_size_align(STRING_HEADER + len, std::mem::align_of::<usize>())
+        .expect("string too large")
+}
+
+/// Copies `bytes` into a buffer that [take_string] frees.
+fn alloc_string(bytes: &[u8]) -> *mut u8 {
+    let layout = string_layout(bytes.len());
+    let base = unsafe { std::alloc::alloc(layout) };
+    if base.is_null() {
+        std::alloc::handle_alloc_error(layout);
+    }
+    unsafe {
+        (base as *mut usize).write(bytes.len());
+        let ptr = base.add(STRING_HEADER);
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len());
+        ptr
+    }
+}
+
+/// Takes a string of `len` bytes allocated by `readline4k_alloc_string`, replacing invalid
+/// UTF-8 with U+FFFD. The string is freed, but discarded if `len` is negative.
+fn take_string(ptr: *mut u8, len: c_int) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    let base = unsafe { ptr.sub(STRING_HEADER) };
+    let size = unsafe { (base as *const usize).read() };
+    let string = usize::try_from(len).ok().map(|len| {
+        let bytes = unsafe { std::slice::from_raw_parts(ptr, len.min(size)) };
+        String::from_utf8_lossy(bytes).into_owned()
+    });
+    unsafe { std::alloc::dealloc(base, string_layout(size)) };
+    string
+}
+
+/// Converts an error message, truncating it at the first interior NUL byte instead of failing.
+fn to_c_string(s: &str) -> CString {
+    match CString::new(s) {
+        Ok(c_string) => c_string,
+        Err(err) => {
+            let pos = err.nul_position();
+            let mut bytes = err.into_vec();
+            bytes.truncate(pos);
+            // SAFETY: `bytes` ends right before the first NUL byte.
+            unsafe { CString::from_vec_unchecked(bytes) }
+        }
+    }
+}
+
+/// Borrows the `len` bytes at `ptr`, which may be null if `len` is 0.
+fn buf_to_bytes<'a>(ptr: *const u8, len: c_int) -> Result<&'a [u8], FfiError> {
+    if (ptr.is_null() && len != 0) || len < 0 {
+        return Err(FfiError::invalid_argument("Invalid buffer"));
+    }
+    if len == 0 {
+        return Ok(&[]);
+    }
+    Ok(unsafe { std::slice::from_raw_parts(ptr, len as usize) })
+}
+
+/// Borrows the UTF-8 string of `len` bytes at `ptr`, failing if it is not valid UTF-8.
+fn buf_to_str<'a>(ptr: *const u8, len: c_int) -> Result<&'a str, FfiError> {
+    std::str::from_utf8(buf_to_bytes(ptr, len)?).map_err(|err| {
+        FfiError::new(
+            ERROR_INVALID_ARGUMENT,
+            format!("Invalid UTF-8 string: {}", err),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: i32, ch: u32, modifiers: u8) -> EditorKeyEvent {
+        EditorKeyEvent {
+            code,
+            ch,
+            modifiers,
+        }
+    }
+
+    fn command(kind: i32) -> EditorCommand {
+        EditorCommand {
+            kind,
+            movement: 0,
+            repeat: 0,
+            ch: 0,
+            text: null_mut(),
+            text_len: 0,
+            flag: false,
+        }
+    }
+
+    fn encode(key: KeyEvent) -> Result<Vec<u8>, c_int> {
+        let mut out = Vec::new();
+        encode_key(key, &mut out).map_err(|err| err.error)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn maps_key_events() {
+        let ctrl_x = map_key_event(&key(0, 'x' as u32, 4)).ok();
+        assert_eq!(ctrl_x, Some(KeyEvent(KeyCode::Char('x'), Modifiers::CTRL)));
+        let shift_alt_f5 = map_key_event(&key(8, 5, 3)).ok();
+        assert_eq!(
+            shift_alt_f5,
+            Some(KeyEvent(KeyCode::F(5), Modifiers::SHIFT | Modifiers::ALT))
+        );
+        assert_eq!(
+            map_key_event(&key(16, 0, 0)).ok(),
+            Some(KeyEvent(KeyCode::Up, Modifiers::NONE))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_key_events() {
+        for key in [
+            key(8, 0, 0),
+            key(8, 256, 0),
+            key(0, 0xd800, 0),
+            key(17, 0, 0),
+        ] {
+            let error = map_key_event(&key).err().map(|err| err.error);
+            assert_eq!(error, Some(ERROR_INVALID_ARGUMENT));
+        }
+    }
+
+    #[test]
+    fn encodes_keys_like_a_terminal() {
+        let event = |code, modifiers| KeyEvent(code, modifiers);
+        assert_eq!(
+            encode(event(KeyCode::Char('é'), Modifiers::NONE)),
+            Ok("é".into())
+        );
+        assert_eq!(
+            encode(event(KeyCode::Char('a'), Modifiers::CTRL)),
+            Ok(vec![0x01])
+        );
+        assert_eq!(
+            encode(event(KeyCode::Char('b'), Modifiers::ALT)),
+            Ok(b"\x1bb".to_vec())
+        );
+        assert_eq!(
+            encode(event(KeyCode::Char('a'), Modifiers::SHIFT)),
+            Ok(b"A".to_vec())
+        );
+        assert_eq!(
+            encode(event(KeyCode::Tab, Modifiers::SHIFT)),
+            Ok(b"\x1b[Z".to_vec())
+        );
+        assert_eq!(
+            encode(event(KeyCode::Left, Modifiers::NONE)),
+            Ok(b"\x1b[D".to_vec())
+        );
+        assert_eq!(
+            encode(event(KeyCode::Left, Modifiers::CTRL)),
+            Ok(b"\x1b[1;5D".to_vec())
+        );
+        assert_eq!(
+            encode(event(KeyCode::F(1), Modifiers::NONE)),
+            Ok(b"\x1bOP".to_vec())
+        );
+        assert_eq!(
+            encode(event(KeyCode::F(5), Modifiers::SHIFT)),
+            Ok(b"\x1b[15;2~".to_vec())
+        );
+        assert_eq!(
+            encode(event(KeyCode::Enter, Modifiers::NONE)),
+            Ok(b"\r".to_vec())
+        );
+    }
+
+    #[test]
+    fn rejects_keys_a_terminal_cannot_send() {
+        let event = |code, modifiers| KeyEvent(code, modifiers);
+        for key in [
+            event(KeyCode::Char('1'), Modifiers::SHIFT),
+            event(KeyCode::Char('1'), Modifiers::CTRL),
+            event(KeyCode::F(13), Modifiers::NONE),
+            event(KeyCode::Enter, Modifiers::CTRL),
+        ] {
+            assert_eq!(encode(key), Err(ERROR_INVALID_ARGUMENT), "{key:?}");
+        }
+    }
+
+    #[test]
+    fn maps_commands() {
+        assert_eq!(map_command(&command(2)).ok(), Some(Cmd::AcceptLine));
+        let text = "héllo";
+        let insert = EditorCommand {
+            repeat: 2,
+            text: text.as_ptr(),
+            text_len: text.len() as c_int,
+            ..command(18)
+        };
+        assert_eq!(
+            map_command(&insert).ok(),
+            Some(Cmd::Insert(2, text.to_string()))
+        );
+        let kill = EditorCommand {
+            movement: 3,
+            ..command(20)
+        };
+        let backward_word = Movement::BackwardWord(1, Word::Emacs);
+        assert_eq!(map_command(&kill).ok(), Some(Cmd::Kill(backward_word)));
+        let yank = EditorCommand {
+            flag: true,
+            ..command(32)
+        };
+        assert_eq!(map_command(&yank).ok(), Some(Cmd::Yank(1, Anchor::Before)));
+    }
+
+    #[test]
+    fn rejects_invalid_commands() {
+        let invalid_text = [0xff];
+        for cmd in [
+            command(38),
+            EditorCommand {
+                movement: 13,
+                ..command(21)
+            },
+            EditorCommand {
+                text: invalid_text.as_ptr(),
+                text_len: 1,
+                ..command(18)
+            },
+            EditorCommand {
+                text_len: 1,
+                ..command(18)
+            },
+        ] {
+            let error = map_command(&cmd).err().map(|err| err.error);
+            assert_eq!(error, Some(ERROR_INVALID_ARGUMENT));
+        }
+    }
+
+    #[test]
+    fn registry_rejects_freed_and_stale_handles() {
+        let mut registry = Registry::new();
+        let first = registry.insert(Arc::new("first"));
+        assert_ne!(first, 0);
+        assert_eq!(registry.get(first).as_deref(), Some(&"first"));
+        assert_eq!(registry.remove(first).as_deref(), Some(&"first"));
+        assert!(registry.get(first).is_none());
+        assert!(registry.remove(first).is_none());
+        // The slot is reused with a new generation, the old handle stays invalid.
+        let second = registry.insert(Arc::new("second"));
+        assert_eq!(second as u32, first as u32);
+        assert_ne!(second, first);
+        assert!(registry.get(first).is_none());
+        assert_eq!(registry.get(second).as_deref(), Some(&"second"));
+        assert!(registry.get(0).is_none());
+    }
+
+    #[test]
+    fn completion_start_falls_back_to_pos() {
+        let line = "héllo wörld";
+        assert_eq!(completion_start(line, 7, 7), 7);
+        assert_eq!(completion_start(line, 7, 0), 0);
+        // Inside 'é', after `pos`, and negative.
+        assert_eq!(completion_start(line, 7, 2), 7);
+        assert_eq!(completion_start(line, 7, 8), 7);
+        assert_eq!(completion_start(line, 7, -1), 7);
+    }
+
+    #[test]
+    fn takes_strings_allocated_by_the_library() {
+        let alloc = |s: &str| readline4k_alloc_string(s.as_ptr(), s.len() as c_int);
+        assert_eq!(take_string(alloc("héllo"), 6).as_deref(), Some("héllo"));
+        assert_eq!(take_string(alloc("héllo"), 1).as_deref(), Some("h"));
+        // A wrong length is bounded by the allocation, or discards the string.
+        assert_eq!(take_string(alloc("abc"), 10).as_deref(), Some("abc"));
+        assert_eq!(take_string(alloc("abc"), -1), None);
+        let empty = alloc("");
+        assert!(!empty.is_null());
+        assert_eq!(take_string(empty, 0).as_deref(), Some(""));
+        assert_eq!(take_string(null_mut(), 3), None);
+        assert!(readline4k_alloc_string(null_mut(), 3).is_null());
+        readline4k_free_string(alloc("abc"), 3);
+    }
+
+    #[test]
+    fn takes_candidates_allocated_by_the_library() {
+        extern "C" fn completer(
+            _holder: *mut c_void,
+            _line: *const u8,
+            _line_len: c_int,
+            _pos: c_int,
+            out_start: *mut c_int,
+            out_len: *mut c_int,
+        ) -> *mut CompletionCandidate {
+            let alloc = |s: &str| readline4k_alloc_string(s.as_ptr(), s.len() as c_int);
+            let candidates = readline4k_alloc_candidates(2);
+            let items = unsafe { std::slice::from_raw_parts_mut(candidates, 2) };
+            items[0].replacement = alloc("wörld");
+            items[0].replacement_len = 6;
+            items[0].description = alloc("planet");
+            items[0].description_len = 6;
+            // Skipped, without a replacement.
+            items[1].display = alloc("nothing");
+            items[1].display_len = 7;
+            unsafe {
+                *out_start = 7; // bytes of "héllo "
+                *out_len = 2;
+            }
+            candidates
+        }
+        assert!(readline4k_alloc_candidates(0).is_null());
+        let helper = CustomHelper {
+            completer_cb: Some(completer),
+            ..CustomHelper::default()
+        };
+        let history = MemHistory::new();
+        let ctx = rustyline::Context::new(&history);
+        let (start, candidates) = helper.complete("héllo w", 8, &ctx).unwrap();
+        assert_eq!(start, 7);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].replacement, "wörld");
+        assert_eq!(candidates[0].display, "wörld  -- planet");
+    }
+
+    #[test]
+    fn rejects_out_of_range_settings() {
+        assert_eq!(map_keyseq_timeout(-1).ok(), Some(None));
+        assert_eq!(map_keyseq_timeout(500).ok(), Some(Some(500)));
+        assert!(map_keyseq_timeout(-2).is_err());
+        assert!(map_keyseq_timeout(65_536).is_err());
+        assert_eq!(map_size("max_history_size", 0).ok(), Some(0));
+        assert!(map_size("max_history_size", -1).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "sqlite-history")]
+    fn shares_sqlite_history_with_details() {
+        let path = std::env::temp_dir().join(format!(
+            "readline4k_test_{}_history.sqlite3",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let path_str = path.to_str().unwrap();
+        let config = Config::default();
+        let open = || {
+            EditorHistory::sqlite(path_str.as_ptr(), path_str.len() as c_int, &config)
+                .ok()
+                .unwrap()
+        };
+        let mut first = open();
+        let mut second = open();
+        assert!(first.add("from first").unwrap());
+        assert!(second.add("from second").unwrap());
+        // Another editor sees the entries of both, each recorded in its own session.
+        let reopened = open();
+        assert_eq!(reopened.len(), 2);
+        let (entry, session_id, timestamp) = reopened.details(1).ok().flatten().unwrap();
+        assert_eq!(entry, "from second");
+        assert_eq!(Some(session_id), second.session_id().ok());
+        assert_ne!(Some(session_id), first.session_id().ok());
+        assert!(timestamp > 0);
+        assert_eq!(reopened.session_id().ok(), Some(0));
+        drop((first, second, reopened));
+        std::fs::remove_file(&path).unwrap();
+    }
 }